@@ -0,0 +1,115 @@
+use super::path::{self, PathsOperation};
+use super::pattern;
+use super::strings;
+use super::tags;
+use super::time;
+use super::transform::*;
+use super::value::Value;
+use super::walk::{self, WalkOperation, WalkOrder};
+
+type Op = Box<dyn Operation>;
+
+/// A builtin which works on values: it receives the input and the results of
+/// running each of its argument expressions against that same input.
+type Function = fn(Value, Vec<Value>) -> OperationResult;
+
+/// Value builtins, with the number of arguments each takes.
+const FUNCTIONS: &[(&str, usize, Function)] = &[
+    ("inst", 0, time::inst),
+    ("now", 0, time::now),
+    ("to-millis", 0, time::to_millis),
+    ("from-millis", 0, time::from_millis),
+    ("duration", 1, time::duration),
+    ("truncate", 1, time::truncate),
+    ("format-inst", 1, time::format_inst),
+    ("tag", 0, tags::tag_of),
+    ("tag", 1, tags::tag),
+    ("untag", 0, tags::untag),
+    ("retag", 2, tags::retag),
+    ("recurse", 0, walk::recurse),
+    ("recurse", 1, walk::recurse_keys),
+    ("paths", 0, path::paths),
+    ("leaf-paths", 0, path::leaf_paths),
+    ("get-path", 1, path::get_path),
+    ("set-path", 2, path::set_path),
+    ("del-paths", 1, path::del_paths),
+    ("merge", 1, merge),
+    ("deep-merge", 0, deep_merge_all),
+    ("deep-merge", 1, deep_merge_all),
+    ("deep-merge", 2, deep_merge_all),
+    ("test", 1, pattern::test),
+    ("re-find", 1, pattern::re_find),
+    ("re-matches", 1, pattern::re_matches),
+    ("re-seq", 1, pattern::re_seq),
+    ("capture", 1, pattern::capture),
+    ("replace", 2, pattern::replace),
+    ("replace-all", 2, pattern::replace_all),
+    ("split", 1, pattern::split),
+    ("upper-case", 0, strings::upper_case),
+    ("lower-case", 0, strings::lower_case),
+    ("trim", 0, strings::trim),
+    ("blank?", 0, strings::is_blank),
+    ("starts-with?", 1, strings::starts_with),
+    ("ends-with?", 1, strings::ends_with),
+    ("includes?", 1, strings::includes),
+    ("subs", 1, strings::subs),
+    ("subs", 2, strings::subs),
+    ("join", 0, strings::join),
+    ("join", 1, strings::join),
+    ("str", 0, strings::str_of),
+    ("keyword", 0, strings::keyword),
+    ("symbol", 0, strings::symbol),
+    ("name", 0, strings::name),
+    ("namespace", 0, strings::namespace),
+];
+
+crate struct FunctionOperation {
+    function: Function,
+    args: Vec<Op>,
+}
+
+impl Operation for FunctionOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.execute(input.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        (self.function)(input, args)
+    }
+}
+
+/// Resolves a call such as `keys` or `map(:a)` to its operation. Arguments
+/// are separated by `;`, as in jq. `None` when there is no builtin of that
+/// name taking that many arguments.
+crate fn lookup(name: &str, mut args: Vec<Op>) -> Option<Op> {
+    let op: Op = match (name, args.len()) {
+        ("keys", 0) => Box::new(KeysOperation {}),
+        ("values", 0) => Box::new(ValuesOperation {}),
+        ("map", 1) => Box::new(MapOperation { op: args.remove(0) }),
+        ("select", 1) => Box::new(SelectOperation {
+            predicate: args.remove(0),
+        }),
+        ("paths", 1) => Box::new(PathsOperation {
+            predicate: args.remove(0),
+        }),
+        ("prewalk", 1) => Box::new(WalkOperation {
+            order: WalkOrder::Pre,
+            op: args.remove(0),
+        }),
+        ("postwalk", 1) | ("walk", 1) => Box::new(WalkOperation {
+            order: WalkOrder::Post,
+            op: args.remove(0),
+        }),
+        _ => {
+            let &(_, _, function) = FUNCTIONS
+                .iter()
+                .find(|(n, arity, _)| *n == name && *arity == args.len())?;
+
+            Box::new(FunctionOperation { function, args })
+        }
+    };
+
+    Some(op)
+}
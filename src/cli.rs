@@ -1,41 +1,212 @@
-use clap::{value_t, App, Arg};
+use clap::{value_t, App, Arg, ArgMatches, ErrorKind};
 
+use super::diff;
 use super::input;
 use super::output;
+use super::parse::Variables;
+use super::tags::TagRegistry;
+use super::theme;
 use super::transform;
+use super::value::Value;
 
 crate struct EqOptions {
     crate input: input::InputOptions,
     crate output: output::OutputOptions,
     crate transform: transform::TransformOptions,
+    /// Compare two inputs with `--diff` instead of running an expression.
+    crate diff: Option<diff::DiffOptions>,
+    /// Deep merge several inputs into one with `--merge`.
+    crate merge: Option<transform::MergeOptions>,
+    /// Set the exit code from the truthiness of the last output, like jq's
+    /// `-e`.
+    crate exit_status: bool,
 }
 
-crate fn parse_opts() -> EqOptions {
-    let matches = App::new("eq (jq for edn)")
+/// Expands the escapes a separator is likely to need, since shells make it
+/// awkward to pass control characters directly.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('0') => unescaped.push('\0'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn separator(matches: &ArgMatches<'_>) -> String {
+    if matches.is_present("join_output") {
+        String::new()
+    } else if matches.is_present("nul") {
+        "\0".to_owned()
+    } else {
+        matches
+            .value_of("separator")
+            .map_or_else(|| "\n".to_owned(), unescape)
+    }
+}
+
+/// Collects `--arg` and `--argedn` bindings. When a name is bound by both,
+/// the `--argedn` value wins.
+fn variables(matches: &ArgMatches<'_>) -> Result<Variables, clap::Error> {
+    let mut vars = Variables::new();
+
+    let pairs = |name: &str| -> Vec<(String, String)> {
+        let values: Vec<&str> = matches.values_of(name).map_or(vec![], |v| v.collect());
+        values
+            .chunks(2)
+            .map(|pair| (pair[0].to_owned(), pair[1].to_owned()))
+            .collect()
+    };
+
+    for (name, value) in pairs("arg") {
+        vars.insert(name, Value::String(value));
+    }
+
+    for (name, edn) in pairs("argedn") {
+        let mut forms = input::parse_edn(&edn).map_err(|e| {
+            let message = format!("--argedn {}: invalid EDN: {}", name, e.describe(&edn));
+            clap::Error::with_description(&message, ErrorKind::InvalidValue)
+        })?;
+        if forms.len() != 1 {
+            return Err(clap::Error::with_description(
+                &format!("--argedn {}: expected a single EDN value, got '{}'", name, edn),
+                ErrorKind::InvalidValue,
+            ));
+        }
+        vars.insert(name, forms.remove(0));
+    }
+
+    Ok(vars)
+}
+
+fn tags(matches: &ArgMatches<'_>) -> Result<TagRegistry, clap::Error> {
+    let mut registry = TagRegistry::default();
+
+    for declaration in matches.values_of("tag").into_iter().flatten() {
+        registry.declare(declaration).map_err(|e| {
+            let message = format!("--tag {}: {}", declaration, e);
+            clap::Error::with_description(&message, ErrorKind::InvalidValue)
+        })?;
+    }
+
+    Ok(registry)
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("eq (jq for edn)")
         .version("0.1")
         .author("Joe Einertson <joe@einertson>")
         .about("jq for EDN")
         .arg(
             Arg::with_name("expression")
                 .help("eq expression which will transform the input")
-                .required(true)
+                .required_unless_one(&["diff", "merge"])
                 .index(1),
         )
         .arg(
             Arg::with_name("input_path")
                 .help("Input path (JSON, EDN, etc.) to read from")
-                .required(true)
+                .required_unless_one(&["null_input", "diff", "merge"])
                 .index(2),
         )
+        .arg(
+            Arg::with_name("diff")
+                .help("Print the structural differences between two inputs instead of transforming one")
+                .long("diff")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["old", "new"])
+                .conflicts_with_all(&["expression", "input_path", "null_input", "slurp"]),
+        )
+        .arg(
+            Arg::with_name("diff_format")
+                .help("How --diff writes changes: as text, or as [:changed path old new] EDN forms")
+                .long("diff-format")
+                .takes_value(true)
+                .case_insensitive(true)
+                .default_value("text")
+                .possible_values(&diff::DiffFormat::variants()),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .help("Deep merge every form of this input into those before it; repeat for each input (--merge a --merge b), then transform the result (the expression defaults to .)")
+                .long("merge")
+                .takes_value(true)
+                .value_name("input")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["input_path", "null_input", "slurp", "diff"]),
+        )
+        .arg(
+            Arg::with_name("merge_vectors")
+                .help("How --merge and deep-merge combine vectors and lists")
+                .long("merge-vectors")
+                .takes_value(true)
+                .case_insensitive(true)
+                .default_value("replace")
+                .possible_values(&transform::VectorMerge::variants()),
+        )
+        .arg(
+            Arg::with_name("null_input")
+                .help("Don't read any input; run the expression once with nil as its input")
+                .short("n")
+                .long("null-input"),
+        )
+        .arg(
+            Arg::with_name("arg")
+                .help("Bind $name to the string value in the expression")
+                .long("arg")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["name", "value"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("argedn")
+                .help("Bind $name to the EDN value in the expression")
+                .long("argedn")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["name", "edn"])
+                .multiple(true),
+        )
         .arg(
             Arg::with_name("input_format")
-                .help("Input data format")
+                .help("Input data format (inferred from the file when omitted)")
                 .short("i")
                 .long("input-format")
                 .takes_value(true)
                 .case_insensitive(true)
                 .possible_values(&input::InputFormat::variants()),
         )
+        .arg(
+            Arg::with_name("tag")
+                .help("Validate #name literals as holding kind: inst, uuid, string, integer, number, keyword, symbol, vector, map or any")
+                .long("tag")
+                .takes_value(true)
+                .value_name("name=kind")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("slurp")
+                .help("Read all input forms into a single vector before transforming it")
+                .short("s")
+                .long("slurp"),
+        )
         .arg(
             Arg::with_name("output_style")
                 .help("Output data style")
@@ -45,6 +216,14 @@ crate fn parse_opts() -> EqOptions {
                 .default_value("pretty")
                 .possible_values(&output::OutputStyle::variants()),
         )
+        .arg(
+            Arg::with_name("width")
+                .help("Line width the pretty printer tries to fit output within")
+                .short("w")
+                .long("width")
+                .takes_value(true)
+                .default_value("80"),
+        )
         .arg(
             Arg::with_name("output_format")
                 .help("Output data format")
@@ -65,26 +244,153 @@ crate fn parse_opts() -> EqOptions {
                 .default_value("default")
                 .possible_values(&["default", "always", "never"]),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("theme")
+                .help(
+                    "Color theme: one of default, solarized-dark, light, monochrome, \
+                     or the path to an EDN theme file",
+                )
+                .long("theme")
+                .takes_value(true)
+                .default_value("default"),
+        )
+        .arg(
+            Arg::with_name("rainbow")
+                .help("Color brackets by nesting depth")
+                .long("rainbow"),
+        )
+        .arg(
+            Arg::with_name("canonical")
+                .help("Write deterministic output for hashing and diffing: compact, uncolored, with maps and sets in a fixed order")
+                .long("canonical"),
+        )
+        .arg(
+            Arg::with_name("sort_keys")
+                .help("Sort the keys of JSON objects")
+                .short("S")
+                .long("sort-keys"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .help("Write top-level strings, keywords and symbols without quotes or sigils")
+                .short("r")
+                .long("raw-output"),
+        )
+        .arg(
+            Arg::with_name("join_output")
+                .help("Like --raw-output, but write nothing between outputs")
+                .short("j")
+                .long("join-output"),
+        )
+        .arg(
+            Arg::with_name("nul")
+                .help("Write a NUL byte after each output instead of a newline")
+                .long("nul")
+                .conflicts_with_all(&["join_output", "separator"]),
+        )
+        .arg(
+            Arg::with_name("separator")
+                .help("String to write after each output (\\n, \\t, \\0 and \\\\ are unescaped)")
+                .long("separator")
+                .takes_value(true)
+                .conflicts_with("join_output"),
+        )
+        .arg(
+            Arg::with_name("slurp_output")
+                .help("Write all outputs as a single vector")
+                .long("slurp-output"),
+        )
+        .arg(
+            Arg::with_name("exit_status")
+                .help("Exit with status 1 if the last output is nil or false, or if there is no output")
+                .short("e")
+                .long("exit-status"),
+        )
+}
+
+crate fn parse_opts() -> Result<EqOptions, clap::Error> {
+    let parsed = app().get_matches_safe();
+
+    let matches = match parsed {
+        Ok(m) => m,
+        Err(e) => match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => return Err(e),
+        },
+    };
 
     match matches.value_of("color").unwrap() {
         "always" => colored::control::set_override(true),
         "never" => colored::control::set_override(false),
-        _ => (),
+        _ => {
+            // https://no-color.org
+            if std::env::var_os("NO_COLOR").is_some() {
+                colored::control::set_override(false);
+            }
+        }
     }
 
-    EqOptions {
+    let theme = theme::resolve_theme(matches.value_of("theme").unwrap())
+        .map_err(|e| clap::Error::with_description(&e.to_string(), ErrorKind::InvalidValue))?;
+
+    Ok(EqOptions {
         input: input::InputOptions {
-            format: value_t!(matches.value_of("input_format"), input::InputFormat).unwrap(),
-            path: matches.value_of("input_path").unwrap().into(),
+            format: value_t!(matches.value_of("input_format"), input::InputFormat).ok(),
+            path: matches.value_of("input_path").unwrap_or_default().into(),
+            slurp: matches.is_present("slurp"),
+            null_input: matches.is_present("null_input"),
+            tags: tags(&matches)?,
         },
         output: output::OutputOptions {
             format: value_t!(matches.value_of("output_format"), output::OutputFormat).unwrap(),
             style: value_t!(matches.value_of("output_style"), output::OutputStyle).unwrap(),
+            width: value_t!(matches.value_of("width"), usize)?,
+            theme,
+            rainbow: matches.is_present("rainbow"),
+            canonical: matches.is_present("canonical"),
+            sort_keys: matches.is_present("sort_keys"),
+            raw: matches.is_present("raw") || matches.is_present("join_output"),
+            separator: separator(&matches),
+            slurp_output: matches.is_present("slurp_output"),
             destination: output::OutputDestination::Stdout,
         },
         transform: transform::TransformOptions {
-            expression: matches.value_of("expression").unwrap().into(),
+            expression: matches.value_of("expression").unwrap_or(".").into(),
+            variables: variables(&matches)?,
         },
+        diff: matches.values_of("diff").map(|mut paths| diff::DiffOptions {
+            old_path: paths.next().unwrap().into(),
+            new_path: paths.next().unwrap().into(),
+            format: value_t!(matches.value_of("diff_format"), diff::DiffFormat).unwrap(),
+        }),
+        merge: matches.values_of("merge").map(|paths| transform::MergeOptions {
+            paths: paths.map(String::from).collect(),
+            vectors: value_t!(matches.value_of("merge_vectors"), transform::VectorMerge)
+                .unwrap(),
+        }),
+        exit_status: matches.is_present("exit_status"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a\\tb\\n"), "a\tb\n");
+        assert_eq!(unescape("\\0\\\\x\\"), "\0\\x\\");
+    }
+
+    #[test]
+    fn test_merge_inputs() {
+        let matches = app()
+            .get_matches_from_safe(vec!["eq", "--merge", "a.edn", "--merge", "b.edn", ":db"])
+            .unwrap();
+
+        let inputs: Vec<&str> = matches.values_of("merge").unwrap().collect();
+
+        assert_eq!(inputs, vec!["a.edn", "b.edn"]);
+        assert_eq!(matches.value_of("expression"), Some(":db"));
     }
 }
@@ -0,0 +1,208 @@
+use clap::{_clap_count_exprs, arg_enum};
+
+use super::path::Path;
+use super::value::Value;
+
+arg_enum! {
+    pub enum DiffFormat {
+        Text,
+        EDN,
+    }
+}
+
+crate struct DiffOptions {
+    crate old_path: String,
+    crate new_path: String,
+    crate format: DiffFormat,
+}
+
+/// One difference between two forms.
+#[derive(Debug, Clone, PartialEq)]
+crate enum Change {
+    Added(Path, Value),
+    Removed(Path, Value),
+    Changed(Path, Value, Value),
+}
+
+impl Change {
+    /// The change as EDN data: `[:added path value]`, `[:removed path value]`
+    /// or `[:changed path old new]`.
+    crate fn into_edn(self) -> Value {
+        let keyword = |name: &str| Value::Keyword(name.to_owned());
+
+        Value::Vector(match self {
+            Change::Added(path, value) => vec![keyword("added"), Value::Vector(path), value],
+            Change::Removed(path, value) => vec![keyword("removed"), Value::Vector(path), value],
+            Change::Changed(path, old, new) => {
+                vec![keyword("changed"), Value::Vector(path), old, new]
+            }
+        })
+    }
+}
+
+fn is_collection(value: &Value) -> bool {
+    match value {
+        Value::List(_) | Value::Vector(_) | Value::Set(_) | Value::Map(_) => true,
+        _ => false,
+    }
+}
+
+fn child(path: &[Value], key: Value) -> Path {
+    let mut child = path.to_vec();
+    child.push(key);
+    child
+}
+
+fn diff_items(path: &[Value], old: Vec<Value>, new: Vec<Value>, changes: &mut Vec<Change>) {
+    let (old_len, new_len) = (old.len(), new.len());
+    let mut old = old.into_iter();
+    let mut new = new.into_iter();
+
+    for idx in 0..old_len.max(new_len) {
+        let item_path = child(path, Value::Integer(idx as i64));
+
+        match (old.next(), new.next()) {
+            (Some(o), Some(n)) => diff_into(&item_path, o, n, changes),
+            (Some(o), None) => changes.push(Change::Removed(item_path, o)),
+            (None, Some(n)) => changes.push(Change::Added(item_path, n)),
+            (None, None) => {}
+        }
+    }
+}
+
+fn diff_into(path: &[Value], old: Value, new: Value, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (Value::Map(old), Value::Map(mut new)) => {
+            for (key, o) in old {
+                let key_path = child(path, key.clone());
+                match new.remove(&key) {
+                    Some(n) => diff_into(&key_path, o, n, changes),
+                    None => changes.push(Change::Removed(key_path, o)),
+                }
+            }
+            for (key, n) in new {
+                changes.push(Change::Added(child(path, key), n));
+            }
+        }
+        (Value::Vector(old), Value::Vector(new)) | (Value::List(old), Value::List(new)) => {
+            diff_items(path, old, new, changes)
+        }
+        (Value::Set(old), Value::Set(new)) => {
+            for item in old.difference(&new) {
+                changes.push(Change::Removed(child(path, item.clone()), item.clone()));
+            }
+            for item in new.difference(&old) {
+                changes.push(Change::Added(child(path, item.clone()), item.clone()));
+            }
+        }
+        (Value::Tagged(old_tag, old), Value::Tagged(new_tag, new)) => {
+            // Step inside a tag only when it wraps a collection; a changed
+            // `#inst` reads better as a whole.
+            if old_tag == new_tag && is_collection(&old) && is_collection(&new) {
+                diff_into(path, *old, *new, changes)
+            } else {
+                let (old, new) = (Value::Tagged(old_tag, old), Value::Tagged(new_tag, new));
+                changes.push(Change::Changed(path.to_vec(), old, new))
+            }
+        }
+        (old, new) => changes.push(Change::Changed(path.to_vec(), old, new)),
+    }
+}
+
+/// The differences between two forms, by path. Maps are compared key by
+/// key, vectors and lists item by item, and set members are added or removed
+/// with the member itself as the last step of their path, a step which
+/// `get-path` and `del-paths` follow too. Values are compared by EDN
+/// equality, so `1` and `1.0` differ.
+crate fn diff(old: Value, new: Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_into(&[], old, new, &mut changes);
+    changes
+}
+
+/// Compares the forms read from two inputs. Inputs holding a single form are
+/// compared directly; otherwise the forms are compared in turn, with paths
+/// starting at the index of the form.
+crate fn diff_forms(mut old: Vec<Value>, mut new: Vec<Value>) -> Vec<Change> {
+    if old.len() == 1 && new.len() == 1 {
+        diff(old.remove(0), new.remove(0))
+    } else {
+        diff(Value::Vector(old), Value::Vector(new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kw(name: &str) -> Value {
+        Value::Keyword(name.to_owned())
+    }
+
+    fn map_of(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(k, v)| (kw(k), v)).collect())
+    }
+
+    #[test]
+    fn test_diff_maps() {
+        let db = |port| map_of(vec![("port", Value::Integer(port)), ("pool", Value::Integer(10))]);
+        let old = map_of(vec![
+            ("db", db(5432)),
+            ("hosts", Value::Vector(vec![Value::from("a"), Value::from("b")])),
+        ]);
+        let new = map_of(vec![
+            ("db", db(5433)),
+            ("hosts", Value::Vector(vec![Value::from("a")])),
+            ("debug", Value::Boolean(true)),
+        ]);
+        let port = vec![kw("db"), kw("port")];
+
+        assert_eq!(
+            diff(old, new),
+            vec![
+                Change::Changed(port, Value::Integer(5432), Value::Integer(5433)),
+                Change::Removed(vec![kw("hosts"), Value::Integer(1)], Value::from("b")),
+                Change::Added(vec![kw("debug")], Value::Boolean(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_sets_and_tags() {
+        let set = |items: Vec<i64>| Value::Set(items.into_iter().map(Value::Integer).collect());
+        let inst = |s: &str| Value::Tagged("inst".to_owned(), Box::new(Value::from(s)));
+
+        assert_eq!(
+            diff(set(vec![1, 2]), set(vec![2, 3])),
+            vec![
+                Change::Removed(vec![Value::Integer(1)], Value::Integer(1)),
+                Change::Added(vec![Value::Integer(3)], Value::Integer(3)),
+            ]
+        );
+        assert_eq!(diff(inst("2026-01-01"), inst("2026-01-01")), vec![]);
+        assert_eq!(
+            diff(inst("2026-01-01"), inst("2026-01-02")),
+            vec![Change::Changed(vec![], inst("2026-01-01"), inst("2026-01-02"))]
+        );
+    }
+
+    #[test]
+    fn test_into_edn() {
+        let port = vec![kw("db"), kw("port")];
+        let change = Change::Changed(port.clone(), Value::Integer(1), Value::Integer(2));
+
+        assert_eq!(
+            change.into_edn(),
+            Value::Vector(vec![
+                kw("changed"),
+                Value::Vector(port),
+                Value::Integer(1),
+                Value::Integer(2),
+            ])
+        );
+    }
+}
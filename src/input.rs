@@ -1,29 +1,157 @@
-use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
 
 use clap::{_clap_count_exprs, arg_enum};
+use colored::*;
 use edn::parser::Parser;
-use edn::Value as EdnValue;
 use serde_json;
 use serde_json::Result as JsonResult;
 use serde_json::Value as JsonValue;
 
+use super::map::{self, Map};
+use super::number;
+use super::tags::{TagError, TagRegistry};
+use super::transit;
+use super::value::Value as EdnValue;
+
+/// A 1-based line and column in an input file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate struct Position {
+    crate line: usize,
+    crate column: usize,
+}
+
+/// A parse failure as reported by one of the format readers, located either
+/// by byte offset or by line and column, whichever the parser provides.
+#[derive(Debug)]
+crate struct ParseFailure {
+    offset: Option<usize>,
+    position: Option<Position>,
+    message: String,
+}
+
+impl ParseFailure {
+    /// The failure's message and, when known, where in `contents` it
+    /// happened.
+    crate fn describe(&self, contents: &str) -> String {
+        let position = self
+            .position
+            .or_else(|| self.offset.map(|o| offset_to_position(contents, o)));
+
+        match position {
+            Some(pos) => format!("{} at line {}, column {}", self.message, pos.line, pos.column),
+            None => self.message.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 crate enum ReadError {
-    IOError,
-    ParseError(String),
+    IOError {
+        path: String,
+        cause: io::Error,
+    },
+    ParseError {
+        path: String,
+        format: InputFormat,
+        position: Option<Position>,
+        snippet: Option<String>,
+        message: String,
+    },
+    TagError {
+        path: String,
+        cause: TagError,
+    },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::IOError { path, cause } => {
+                write!(f, "{} could not read {}: {}", "error:".red().bold(), path, cause)
+            }
+            ReadError::TagError { path, cause } => write!(
+                f,
+                "{} invalid tagged literal in {}: {}",
+                "error:".red().bold(),
+                path,
+                cause
+            ),
+            ReadError::ParseError {
+                path,
+                format,
+                position,
+                snippet,
+                message,
+            } => {
+                write!(
+                    f,
+                    "{} failed to parse {} input: {}",
+                    "error:".red().bold(),
+                    format,
+                    message
+                )?;
+
+                let pos = match position {
+                    Some(pos) => pos,
+                    None => return write!(f, "\n  {} {}", "-->".blue().bold(), path),
+                };
+
+                write!(
+                    f,
+                    "\n  {} {}:{}:{}",
+                    "-->".blue().bold(),
+                    path,
+                    pos.line,
+                    pos.column
+                )?;
+
+                if let Some(line) = snippet {
+                    let gutter = pos.line.to_string();
+                    let padding = " ".repeat(gutter.len());
+                    let bar = "|".blue().bold();
+
+                    write!(f, "\n{} {}", padding, bar)?;
+                    write!(f, "\n{} {} {}", gutter.blue().bold(), bar, line)?;
+                    write!(
+                        f,
+                        "\n{} {} {}{}",
+                        padding,
+                        bar,
+                        " ".repeat(pos.column - 1),
+                        "^".red().bold()
+                    )?;
+                }
+
+                Ok(())
+            }
+        }
+    }
 }
 
 arg_enum!{
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum InputFormat {
         EDN,
         JSON,
+        JSONL,
+        Transit,
     }
 }
 
 crate struct InputOptions {
-    crate format: InputFormat,
+    /// Explicit input format; when absent it is inferred from the path and
+    /// contents of the input.
+    crate format: Option<InputFormat>,
     crate path: String,
+    /// Collect every form read into a single vector, so the expression runs
+    /// once over the whole input.
+    crate slurp: bool,
+    /// Skip reading and run the expression once with `nil` as its input.
+    crate null_input: bool,
+    /// Tags whose literals are validated as they are read.
+    crate tags: TagRegistry,
 }
 
 fn json_to_edn(json: JsonValue) -> EdnValue {
@@ -31,19 +159,9 @@ fn json_to_edn(json: JsonValue) -> EdnValue {
         JsonValue::Null => EdnValue::Nil,
         JsonValue::Bool(b) => EdnValue::Boolean(b),
         JsonValue::String(s) => EdnValue::String(s),
-        JsonValue::Number(n) => {
-            if n.is_i64() {
-                EdnValue::from(n.as_i64().unwrap())
-            } else if n.is_u64() {
-                EdnValue::from(n.as_i64().unwrap())
-            } else if n.is_f64() {
-                EdnValue::from(n.as_f64().unwrap())
-            } else {
-                unreachable!()
-            }
-        }
+        JsonValue::Number(n) => number::from_json(&n),
         JsonValue::Object(n) => {
-            let mut acc: BTreeMap<EdnValue, EdnValue> = BTreeMap::new();
+            let mut acc = Map::new();
 
             for (k, v) in n {
                 acc.insert(EdnValue::from(k), json_to_edn(v));
@@ -63,43 +181,316 @@ fn json_to_edn(json: JsonValue) -> EdnValue {
     }
 }
 
-fn parse_json(contents: &str) -> Result<Vec<EdnValue>, ReadError> {
-    let parsed: JsonResult<JsonValue> = serde_json::from_str(contents);
+/// Builds a failure from a serde_json error, dropping the position suffix
+/// serde_json adds to its messages since we report it separately.
+fn json_failure(e: &serde_json::Error, line: usize) -> ParseFailure {
+    let message = e.to_string();
+    let suffix = format!(" at line {} column {}", e.line(), e.column());
+
+    ParseFailure {
+        offset: None,
+        position: Some(Position {
+            line,
+            column: e.column().max(1),
+        }),
+        message: message.trim_right_matches(suffix.as_str()).to_owned(),
+    }
+}
+
+/// Parses any number of whitespace-separated (or simply concatenated) JSON
+/// values, yielding one form per value.
+fn parse_json(contents: &str) -> Result<Vec<EdnValue>, ParseFailure> {
+    let stream = serde_json::Deserializer::from_str(contents).into_iter::<JsonValue>();
     let mut forms: Vec<EdnValue> = Vec::new();
 
-    match parsed {
-        Ok(json) => forms.push(json_to_edn(json)),
-        Err(e) => {
-            return Err(ReadError::ParseError(format!(
-                "Failed to parse JSON: {}",
-                e
-            )))
+    for parsed in stream {
+        match parsed {
+            Ok(json) => forms.push(json_to_edn(json)),
+            Err(e) => return Err(json_failure(&e, e.line())),
         }
     }
 
     Ok(forms)
 }
 
-fn parse_edn(contents: &str) -> Result<Vec<EdnValue>, ReadError> {
-    let mut parser = Parser::new(&contents);
+/// Parses strict JSON Lines, where every non-blank line must hold exactly one
+/// JSON value.
+fn parse_json_lines(contents: &str) -> Result<Vec<EdnValue>, ParseFailure> {
+    let mut forms: Vec<EdnValue> = Vec::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed: JsonResult<JsonValue> = serde_json::from_str(line);
+
+        match parsed {
+            Ok(json) => forms.push(json_to_edn(json)),
+            Err(e) => return Err(json_failure(&e, idx + 1)),
+        }
+    }
+
+    Ok(forms)
+}
+
+fn parse_transit(contents: &str) -> Result<Vec<EdnValue>, ParseFailure> {
+    let parsed: JsonResult<JsonValue> = serde_json::from_str(contents);
+
+    match parsed {
+        Ok(json) => match transit::decode(json) {
+            Ok(form) => Ok(vec![form]),
+            Err(e) => Err(ParseFailure {
+                offset: None,
+                position: None,
+                message: e.to_string(),
+            }),
+        },
+        Err(e) => Err(json_failure(&e, e.line())),
+    }
+}
+
+/// Checks that every map, read as an `#eq/map` vector, has a value for each
+/// of its keys.
+fn check_maps(form: &edn::Value) -> Result<(), ParseFailure> {
+    match form {
+        edn::Value::Tagged(tag, inner) => {
+            if let edn::Value::Vector(items) = &**inner {
+                if let (map::EDN_MAP_TAG, Some((edn::Value::Integer(offset), entries))) =
+                    (tag.as_str(), items.split_first())
+                {
+                    if entries.len() % 2 != 0 {
+                        return Err(ParseFailure {
+                            offset: Some(*offset as usize),
+                            position: None,
+                            message: "Map literal must contain an even number of forms"
+                                .to_owned(),
+                        });
+                    }
+                }
+            }
+            check_maps(inner)
+        }
+        edn::Value::List(items) | edn::Value::Vector(items) => {
+            for item in items {
+                check_maps(item)?;
+            }
+            Ok(())
+        }
+        edn::Value::Set(items) => {
+            for item in items {
+                check_maps(item)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+crate fn parse_edn(contents: &str) -> Result<Vec<EdnValue>, ParseFailure> {
+    let rewritten = number::rewrite_literals(contents);
+    let mut parser = Parser::new(&rewritten.text);
     let mut forms: Vec<EdnValue> = Vec::new();
 
     while let Some(form) = parser.read() {
         match form {
-            Ok(f) => forms.push(f),
-            Err(_) => return Err(ReadError::ParseError("Failed to parse EDN".into())),
+            Ok(f) => {
+                check_maps(&f)?;
+                forms.push(EdnValue::from(f))
+            }
+            Err(e) => {
+                return Err(ParseFailure {
+                    offset: Some(rewritten.original_offset(e.lo)),
+                    position: None,
+                    message: e.message,
+                })
+            }
         }
     }
 
     Ok(forms)
 }
 
+/// Converts a byte offset into a line and column, counting columns in
+/// characters.
+fn offset_to_position(contents: &str, offset: usize) -> Position {
+    let mut offset = offset.min(contents.len());
+    while !contents.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &contents[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// The text of a 1-based line, with tabs widened to single spaces so that a
+/// caret lines up underneath it.
+fn source_line(contents: &str, line: usize) -> Option<String> {
+    contents
+        .lines()
+        .nth(line - 1)
+        .map(|l| l.replace('\t', " "))
+}
+
+fn format_from_extension(path: &str) -> Option<InputFormat> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+
+    match extension.as_str() {
+        "edn" => Some(InputFormat::EDN),
+        "json" => Some(InputFormat::JSON),
+        "jsonl" | "ndjson" => Some(InputFormat::JSONL),
+        "transit" => Some(InputFormat::Transit),
+        _ => None,
+    }
+}
+
+/// Guesses the format from the first non-whitespace bytes. Anything which
+/// does not start with a valid JSON value is treated as EDN.
+fn sniff_format(contents: &str) -> InputFormat {
+    let trimmed = contents.trim_left();
+
+    if trimmed.starts_with("[\"^ \"") || trimmed.starts_with("[\"~#") {
+        return InputFormat::Transit;
+    }
+
+    if trimmed.starts_with('{') || trimmed.starts_with('[') || trimmed.starts_with('"') {
+        let mut stream = serde_json::Deserializer::from_str(trimmed).into_iter::<JsonValue>();
+
+        if let Some(Ok(_)) = stream.next() {
+            return InputFormat::JSON;
+        }
+    }
+
+    InputFormat::EDN
+}
+
+fn detect_format(path: &str, contents: &str) -> InputFormat {
+    format_from_extension(path).unwrap_or_else(|| sniff_format(contents))
+}
+
 crate fn read_file(opts: &InputOptions) -> Result<Vec<EdnValue>, ReadError> {
-    let contents = std::fs::read(&opts.path).map_err(|_| ReadError::IOError)?;
+    if opts.null_input {
+        return Ok(vec![EdnValue::Nil]);
+    }
+
+    let contents = std::fs::read(&opts.path).map_err(|e| ReadError::IOError {
+        path: opts.path.clone(),
+        cause: e,
+    })?;
     let as_str = String::from_utf8_lossy(&contents);
+    let format = opts
+        .format
+        .unwrap_or_else(|| detect_format(&opts.path, &as_str));
+
+    let parsed = match format {
+        InputFormat::JSON => parse_json(&as_str),
+        InputFormat::JSONL => parse_json_lines(&as_str),
+        InputFormat::EDN => parse_edn(&as_str),
+        InputFormat::Transit => parse_transit(&as_str),
+    };
+
+    let forms = parsed.map_err(|failure| {
+        let position = failure
+            .position
+            .or_else(|| failure.offset.map(|o| offset_to_position(&as_str, o)));
+
+        ReadError::ParseError {
+            path: opts.path.clone(),
+            format,
+            position,
+            snippet: position.and_then(|p| source_line(&as_str, p.line)),
+            message: failure.message,
+        }
+    })?;
+
+    for form in &forms {
+        opts.tags.validate(form).map_err(|cause| ReadError::TagError {
+            path: opts.path.clone(),
+            cause,
+        })?;
+    }
 
-    Ok(match opts.format {
-        InputFormat::JSON => parse_json(&as_str)?,
-        InputFormat::EDN => parse_edn(&as_str)?,
-    })
+    Ok(forms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::output;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format("events.JSONL", "{}"), InputFormat::JSONL);
+        assert_eq!(detect_format("data", "  {\"a\": [1, 2]}"), InputFormat::JSON);
+        assert_eq!(detect_format("data", "{:a [1 2]}"), InputFormat::EDN);
+        assert_eq!(detect_format("data", "[\"^ \",\"~:a\",1]"), InputFormat::Transit);
+        assert_eq!(detect_format("data", ":kw"), InputFormat::EDN);
+    }
+
+    #[test]
+    fn test_json_stream() {
+        let forms = parse_json("{\"a\": 1}\n{\"a\": 2} [3]4").unwrap();
+        assert_eq!(forms.len(), 4);
+        assert_eq!(forms[3], EdnValue::Integer(4));
+    }
+
+    #[test]
+    fn test_json_key_order() {
+        let forms = parse_json("{\"b\": 1, \"a\": 2, \"c\": 3}").unwrap();
+
+        match &forms[0] {
+            EdnValue::Map(m) => assert_eq!(
+                m.keys().cloned().collect::<Vec<_>>(),
+                vec![EdnValue::from("b"), EdnValue::from("a"), EdnValue::from("c")]
+            ),
+            other => panic!("expected a map, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_edn_key_order() {
+        let text = "{:b 1 :a {\"z\" 2 \"y\" #{3}} :c [{:e 4 :d 5}]}";
+        let forms = parse_edn(text).unwrap();
+
+        match &forms[0] {
+            EdnValue::Map(m) => assert_eq!(
+                m.keys().cloned().collect::<Vec<_>>(),
+                vec![
+                    EdnValue::Keyword("b".to_owned()),
+                    EdnValue::Keyword("a".to_owned()),
+                    EdnValue::Keyword("c".to_owned()),
+                ]
+            ),
+            other => panic!("expected a map, got {:?}", other),
+        }
+        assert_eq!(output::edn_string(forms[0].clone()), text);
+
+        let failure = parse_edn("[1\n {:a{:b}}]").unwrap_err();
+        assert_eq!(failure.offset, Some(7));
+        assert_eq!(
+            offset_to_position("[1\n {:a{:b}}]", 7),
+            Position { line: 2, column: 5 }
+        );
+    }
+
+    #[test]
+    fn test_json_lines_error_line() {
+        let failure = parse_json_lines("1\n\n{\"a\": }\n").unwrap_err();
+        assert_eq!(failure.position.map(|p| p.line), Some(3));
+    }
+
+    #[test]
+    fn test_offset_to_position() {
+        let contents = "{:a 1\n :b [1 2}\n";
+        assert_eq!(
+            offset_to_position(contents, 14),
+            Position { line: 2, column: 9 }
+        );
+        assert_eq!(source_line(contents, 2), Some(" :b [1 2}".to_owned()));
+    }
 }
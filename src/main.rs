@@ -1,32 +1,160 @@
 #![feature(rust_2018_preview)]
 #![warn(rust_2018_idioms)]
 
+use std::fmt;
+use std::io;
+use std::process;
+
+use colored::*;
+
+mod builtins;
 mod cli;
+mod diff;
 mod input;
+mod map;
+mod number;
 mod output;
+mod parse;
+mod path;
+mod pattern;
+mod strings;
+mod tags;
+mod theme;
+mod time;
 mod transform;
+mod transit;
+mod value;
+mod walk;
+
+/// Exit code with `--exit-status` when the last output is `nil`/`false`, or
+/// when there is no output at all.
+const EXIT_FALSY: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_INPUT: i32 = 3;
+const EXIT_EXPRESSION: i32 = 4;
+const EXIT_OPERATION: i32 = 5;
+const EXIT_OUTPUT: i32 = 6;
 
 #[derive(Debug)]
 enum ApplicationError {
+    Usage(clap::Error),
     Read(input::ReadError),
+    Expression(parse::ExpressionError),
     Operation(transform::OperationError),
+    Write(io::Error),
 }
 
-fn main() {
-    let opts = cli::parse_opts();
-
-    let output = input::read_file(&opts.input)
-        .or_else(|e| Err(ApplicationError::Read(e)))
-        .and_then(|c| {
-            transform::transform_edn(c, &opts.transform)
-                .or_else(|e| Err(ApplicationError::Operation(e)))
-        });
-
-    match output {
-        Ok(p) => output::format_output(p, &opts.output).expect("Failed to write output."),
-        Err(ae) => match ae {
-            ApplicationError::Read(w) => println!("FATAL: {:?}", w),
-            ApplicationError::Operation(_o) => (),
-        },
+impl ApplicationError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ApplicationError::Usage(_) => EXIT_USAGE,
+            ApplicationError::Read(_) => EXIT_INPUT,
+            ApplicationError::Expression(_) => EXIT_EXPRESSION,
+            ApplicationError::Operation(_) => EXIT_OPERATION,
+            ApplicationError::Write(_) => EXIT_OUTPUT,
+        }
     }
 }
+
+impl fmt::Display for ApplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplicationError::Usage(e) => write!(f, "{}", e),
+            ApplicationError::Read(e) => write!(f, "{}", e),
+            ApplicationError::Expression(e) => write!(f, "{}", e),
+            ApplicationError::Operation(e) => write!(f, "{}", e),
+            ApplicationError::Write(e) => {
+                write!(f, "{} failed to write output: {}", "error:".red().bold(), e)
+            }
+        }
+    }
+}
+
+/// Reads an input other than the main one, with the main input's format and
+/// tags.
+fn read_input(opts: &cli::EqOptions, path: &str) -> Result<Vec<value::Value>, ApplicationError> {
+    input::read_file(&input::InputOptions {
+        format: opts.input.format,
+        path: path.to_owned(),
+        slurp: false,
+        null_input: false,
+        tags: opts.input.tags.clone(),
+    })
+    .map_err(ApplicationError::Read)
+}
+
+/// Deep merges every form of the `--merge` inputs, in order, into one.
+fn read_merged(
+    opts: &cli::EqOptions,
+    merge: &transform::MergeOptions,
+) -> Result<value::Value, ApplicationError> {
+    let mut merged = value::Value::Nil;
+
+    for path in &merge.paths {
+        for form in read_input(opts, path)? {
+            merged = transform::deep_merge(merged, form, merge.vectors);
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Compares two inputs, returning whether they are the same so that
+/// `--exit-status` fails when they differ.
+fn run_diff(
+    opts: &cli::EqOptions,
+    diff: &diff::DiffOptions,
+) -> Result<Option<bool>, ApplicationError> {
+    let old = read_input(opts, &diff.old_path)?;
+    let new = read_input(opts, &diff.new_path)?;
+    let changes = diff::diff_forms(old, new);
+    let same = changes.is_empty();
+
+    output::format_diff(changes, &diff.format, &opts.output).map_err(ApplicationError::Write)?;
+
+    Ok(Some(same))
+}
+
+/// Runs eq, returning the truthiness of the last output form, if any.
+fn run(opts: &cli::EqOptions) -> Result<Option<bool>, ApplicationError> {
+    if let Some(diff) = &opts.diff {
+        return run_diff(opts, diff);
+    }
+
+    let transform = &opts.transform;
+    let operations = parse::parse_expression(&transform.expression, &transform.variables)
+        .map_err(ApplicationError::Expression)?;
+    let forms = match &opts.merge {
+        Some(merge) => vec![read_merged(opts, merge)?],
+        None => input::read_file(&opts.input).map_err(ApplicationError::Read)?,
+    };
+    let forms = if opts.input.slurp {
+        vec![value::Value::Vector(forms)]
+    } else {
+        forms
+    };
+    let output = transform::transform_edn(forms, &operations)
+        .map_err(ApplicationError::Operation)?;
+    let last_truthy = output.last().map(transform::is_truthy);
+
+    output::format_output(output, &opts.output).map_err(ApplicationError::Write)?;
+
+    Ok(last_truthy)
+}
+
+fn main() {
+    let result = cli::parse_opts()
+        .map_err(ApplicationError::Usage)
+        .and_then(|opts| run(&opts).map(|last| (opts.exit_status, last)));
+
+    let code = match result {
+        Ok((true, last)) if last != Some(true) => EXIT_FALSY,
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    };
+
+    process::exit(code);
+}
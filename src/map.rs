@@ -0,0 +1,172 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::mem;
+use std::vec;
+
+use super::value::Value;
+
+/// Tag of the vectors which stand in for EDN maps while they are read.
+crate const EDN_MAP_TAG: &str = "eq/map";
+
+/// A map which remembers the order in which its keys were first inserted, so
+/// that output can reproduce the key order of the input.
+///
+/// Equality and ordering ignore insertion order: two maps holding the same
+/// entries are equal however they were built.
+///
+/// JSON, Transit and EDN input all keep their key order. The `edn` crate sorts
+/// the maps it reads, so EDN maps reach it rewritten as `#eq/map` vectors of
+/// their offset, keys and values (see `number::rewrite_literals`).
+#[derive(Debug, Clone, Default)]
+crate struct Map {
+    entries: Vec<(Value, Value)>,
+    /// Position of each key in `entries`.
+    index: BTreeMap<Value, usize>,
+}
+
+impl Map {
+    crate fn new() -> Map {
+        Map::default()
+    }
+
+    crate fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    crate fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    crate fn get(&self, key: &Value) -> Option<&Value> {
+        self.index.get(key).map(|&idx| &self.entries[idx].1)
+    }
+
+    crate fn contains_key(&self, key: &Value) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Inserts an entry, returning the previous value for the key. A key which
+    /// is already present keeps its original position.
+    crate fn insert(&mut self, key: Value, value: Value) -> Option<Value> {
+        match self.index.get(&key) {
+            Some(&idx) => Some(mem::replace(&mut self.entries[idx].1, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Removes an entry, preserving the order of the remaining ones.
+    crate fn remove(&mut self, key: &Value) -> Option<Value> {
+        let removed = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(removed);
+
+        for idx in self.index.values_mut() {
+            if *idx > removed {
+                *idx -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Entries in insertion order.
+    crate fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    crate fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    crate fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    /// Entries in key order, regardless of insertion order.
+    crate fn sorted(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.index
+            .iter()
+            .map(move |(k, &idx)| (k, &self.entries[idx].1))
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.len() == other.len() && self.sorted().eq(other.sorted())
+    }
+}
+
+impl Eq for Map {}
+
+impl PartialOrd for Map {
+    fn partial_cmp(&self, other: &Map) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Map {
+    fn cmp(&self, other: &Map) -> Ordering {
+        self.sorted().cmp(other.sorted())
+    }
+}
+
+impl FromIterator<(Value, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Value, Value)>>(iter: I) -> Map {
+        let mut map = Map::new();
+        for (k, v) in iter {
+            map.insert(k, v);
+        }
+        map
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (Value, Value);
+    type IntoIter = vec::IntoIter<(Value, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kw(name: &str) -> Value {
+        Value::Keyword(name.to_owned())
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map: Map = vec![(kw("b"), Value::Integer(1)), (kw("a"), Value::Integer(2))]
+            .into_iter()
+            .collect();
+        map.insert(kw("c"), Value::Integer(3));
+        map.insert(kw("b"), Value::Integer(4));
+        map.remove(&kw("a"));
+
+        assert_eq!(
+            map.clone().into_iter().collect::<Vec<_>>(),
+            vec![(kw("b"), Value::Integer(4)), (kw("c"), Value::Integer(3))]
+        );
+        assert_eq!(map.get(&kw("c")), Some(&Value::Integer(3)));
+    }
+
+    #[test]
+    fn test_equality_ignores_order() {
+        let ab: Map = vec![(kw("a"), Value::Nil), (kw("b"), Value::Nil)]
+            .into_iter()
+            .collect();
+        let ba: Map = vec![(kw("b"), Value::Nil), (kw("a"), Value::Nil)]
+            .into_iter()
+            .collect();
+
+        assert_eq!(ab, ba);
+        assert_eq!(ab.cmp(&ba), Ordering::Equal);
+    }
+}
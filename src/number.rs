@@ -0,0 +1,535 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use serde_json::Number as JsonNumber;
+
+use super::map;
+use super::value::Value;
+
+/// Tag used to smuggle arbitrary-precision literals (`1N`, `1.5M`, `1/3`)
+/// through the `edn` crate's parser, which only understands `i64` and `f64`.
+crate const LITERAL_TAG: &str = "eq/number";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    crate fn name(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+        }
+    }
+}
+
+/// Position of a number in the numeric tower. Arithmetic on two numbers is
+/// carried out at the higher of their two ranks, as in Clojure.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Rank {
+    Integer,
+    BigInt,
+    Ratio,
+    BigDecimal,
+    Float,
+}
+
+fn rank(value: &Value) -> Option<Rank> {
+    match value {
+        Value::Integer(_) => Some(Rank::Integer),
+        Value::BigInt(_) => Some(Rank::BigInt),
+        Value::Ratio(_) => Some(Rank::Ratio),
+        Value::BigDecimal(_) => Some(Rank::BigDecimal),
+        Value::Float(_) => Some(Rank::Float),
+        _ => None,
+    }
+}
+
+fn to_bigint(value: &Value) -> BigInt {
+    match value {
+        Value::Integer(i) => BigInt::from(*i),
+        Value::BigInt(i) => i.clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn to_ratio(value: &Value) -> BigRational {
+    match value {
+        Value::Ratio(r) => r.clone(),
+        _ => BigRational::from_integer(to_bigint(value)),
+    }
+}
+
+fn to_bigdecimal(value: &Value) -> BigDecimal {
+    match value {
+        Value::BigDecimal(d) => d.clone(),
+        Value::Ratio(r) => {
+            BigDecimal::new(r.numer().clone(), 0) / BigDecimal::new(r.denom().clone(), 0)
+        }
+        _ => BigDecimal::new(to_bigint(value), 0),
+    }
+}
+
+fn to_f64(value: &Value) -> f64 {
+    match value {
+        Value::Float(f) => f.into_inner(),
+        Value::Integer(i) => *i as f64,
+        Value::BigInt(i) => i.to_f64().unwrap_or(std::f64::NAN),
+        Value::BigDecimal(d) => d.to_f64().unwrap_or(std::f64::NAN),
+        Value::Ratio(r) => {
+            r.numer().to_f64().unwrap_or(std::f64::NAN)
+                / r.denom().to_f64().unwrap_or(std::f64::NAN)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Demotes a big integer to a plain integer when it fits.
+fn normalize_bigint(i: BigInt) -> Value {
+    match i.to_i64() {
+        Some(small) => Value::Integer(small),
+        None => Value::BigInt(i),
+    }
+}
+
+/// Ratios with a denominator of one are integers.
+fn normalize_ratio(r: BigRational) -> Value {
+    if r.is_integer() {
+        normalize_bigint(r.to_integer())
+    } else {
+        Value::Ratio(r)
+    }
+}
+
+fn apply_integer(op: Operator, a: i64, b: i64) -> Option<Value> {
+    match op {
+        Operator::Add => a.checked_add(b).map(Value::Integer),
+        Operator::Subtract => a.checked_sub(b).map(Value::Integer),
+        Operator::Multiply => a.checked_mul(b).map(Value::Integer),
+        Operator::Divide if a.checked_rem(b) == Some(0) => a.checked_div(b).map(Value::Integer),
+        Operator::Divide => None,
+    }
+}
+
+fn apply_bigint(op: Operator, a: BigInt, b: BigInt) -> Value {
+    match op {
+        Operator::Add => Value::BigInt(a + b),
+        Operator::Subtract => Value::BigInt(a - b),
+        Operator::Multiply => Value::BigInt(a * b),
+        Operator::Divide => normalize_ratio(BigRational::new(a, b)),
+    }
+}
+
+fn apply_ratio(op: Operator, a: BigRational, b: BigRational) -> Value {
+    normalize_ratio(match op {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => a / b,
+    })
+}
+
+fn apply_bigdecimal(op: Operator, a: BigDecimal, b: BigDecimal) -> Value {
+    Value::BigDecimal(match op {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => a / b,
+    })
+}
+
+fn apply_float(op: Operator, a: f64, b: f64) -> Value {
+    Value::from(match op {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => a / b,
+    })
+}
+
+fn is_zero(value: &Value) -> bool {
+    match value {
+        Value::Integer(i) => *i == 0,
+        Value::BigInt(i) => i.is_zero(),
+        Value::Ratio(r) => r.is_zero(),
+        Value::BigDecimal(d) => d.is_zero(),
+        _ => false,
+    }
+}
+
+/// Applies an arithmetic operator to two numbers without losing precision.
+///
+/// Integer overflow promotes to a big integer, and inexact integer division
+/// yields a ratio. Floats are contagious, as are big decimals for every
+/// non-float operand.
+crate fn apply(op: Operator, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let rank = match (rank(lhs), rank(rhs)) {
+        (Some(a), Some(b)) => if a > b { a } else { b },
+        _ => return Err(format!("Can not apply '{}' to non-numeric values", op.name())),
+    };
+
+    if op == Operator::Divide && rank != Rank::Float && is_zero(rhs) {
+        return Err("Divide by zero".to_owned());
+    }
+
+    Ok(match rank {
+        Rank::Integer => match (lhs, rhs) {
+            (Value::Integer(a), Value::Integer(b)) => apply_integer(op, *a, *b)
+                .unwrap_or_else(|| apply_bigint(op, to_bigint(lhs), to_bigint(rhs))),
+            _ => unreachable!(),
+        },
+        Rank::BigInt => apply_bigint(op, to_bigint(lhs), to_bigint(rhs)),
+        Rank::Ratio => apply_ratio(op, to_ratio(lhs), to_ratio(rhs)),
+        Rank::BigDecimal => apply_bigdecimal(op, to_bigdecimal(lhs), to_bigdecimal(rhs)),
+        Rank::Float => apply_float(op, to_f64(lhs), to_f64(rhs)),
+    })
+}
+
+/// Compares two numbers by value across the numeric tower, so that `1`, `1N`
+/// and `1.0` are all equal. `None` when either side is not a number or is NaN.
+crate fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    let rank = match (rank(lhs), rank(rhs)) {
+        (Some(a), Some(b)) => if a > b { a } else { b },
+        _ => return None,
+    };
+
+    match rank {
+        Rank::Integer => match (lhs, rhs) {
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            _ => unreachable!(),
+        },
+        Rank::BigInt => Some(to_bigint(lhs).cmp(&to_bigint(rhs))),
+        Rank::Ratio => Some(to_ratio(lhs).cmp(&to_ratio(rhs))),
+        Rank::BigDecimal => to_bigdecimal(lhs).partial_cmp(&to_bigdecimal(rhs)),
+        Rank::Float => to_f64(lhs).partial_cmp(&to_f64(rhs)),
+    }
+}
+
+/// Parses a numeric EDN literal, including the `N` and `M` suffixes, ratios
+/// and the symbolic floats `##NaN`, `##Inf` and `##-Inf`. Plain integers
+/// which do not fit in an `i64` become big integers.
+crate fn parse_literal(literal: &str) -> Option<Value> {
+    match literal {
+        "##NaN" => return Some(Value::from(std::f64::NAN)),
+        "##Inf" => return Some(Value::from(std::f64::INFINITY)),
+        "##-Inf" => return Some(Value::from(std::f64::NEG_INFINITY)),
+        _ => {}
+    }
+
+    let unsigned = if literal.starts_with('+') || literal.starts_with('-') {
+        &literal[1..]
+    } else {
+        literal
+    };
+
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let literal = literal.trim_left_matches('+');
+
+    if literal.ends_with('N') {
+        BigInt::from_str(&literal[..literal.len() - 1])
+            .ok()
+            .map(Value::BigInt)
+    } else if literal.ends_with('M') {
+        BigDecimal::from_str(&literal[..literal.len() - 1])
+            .ok()
+            .map(Value::BigDecimal)
+    } else if let Some(idx) = literal.find('/') {
+        let numer = BigInt::from_str(&literal[..idx]).ok()?;
+        let denom = BigInt::from_str(&literal[idx + 1..]).ok()?;
+
+        if denom.is_zero() {
+            None
+        } else {
+            Some(normalize_ratio(BigRational::new(numer, denom)))
+        }
+    } else if unsigned.bytes().all(|b| b.is_ascii_digit()) {
+        BigInt::from_str(literal).ok().map(normalize_bigint)
+    } else {
+        f64::from_str(literal).ok().map(Value::from)
+    }
+}
+
+/// Whether a literal is beyond the `edn` crate: one which needs more than an
+/// `i64` or `f64`, or one of the symbolic floats `##NaN`, `##Inf` and
+/// `##-Inf`.
+fn is_extended_literal(literal: &str) -> bool {
+    match parse_literal(literal) {
+        Some(Value::Float(f)) => !f.into_inner().is_finite(),
+        Some(Value::Integer(_)) | None => false,
+        Some(_) => true,
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || ",()[]{}\";".contains(c)
+}
+
+/// A replaced literal: `len` bytes at `at` in the rewritten text stand in for
+/// `original_len` bytes of the original.
+struct Replacement {
+    at: usize,
+    len: usize,
+    original_len: usize,
+}
+
+/// An EDN document with its extended numeric literals rewritten.
+crate struct Rewritten {
+    crate text: String,
+    replacements: Vec<Replacement>,
+}
+
+impl Rewritten {
+    /// Maps a byte offset in the rewritten text back to the original text.
+    crate fn original_offset(&self, offset: usize) -> usize {
+        let mut shift = 0;
+
+        for r in &self.replacements {
+            if offset < r.at {
+                break;
+            } else if offset < r.at + r.len {
+                return r.at - shift;
+            }
+            shift += r.len - r.original_len;
+        }
+
+        offset - shift
+    }
+}
+
+/// Rewrites every arbitrary-precision numeric literal in an EDN document as
+/// `#eq/number "literal"`, and every map as an `#eq/map [offset key value ...]`
+/// vector so that its key order survives the `edn` crate, leaving strings,
+/// characters and comments alone. The offset is that of the map's opening
+/// brace in `contents`, so that errors in the map can still be located.
+crate fn rewrite_literals(contents: &str) -> Rewritten {
+    let mut out = String::with_capacity(contents.len());
+    let mut replacements = vec![];
+    let mut chars = contents.char_indices().peekable();
+    // Whether each open collection is a rewritten map, innermost last.
+    let mut open_maps: Vec<bool> = vec![];
+
+    while let Some((start, c)) = chars.next() {
+        out.push(c);
+
+        match c {
+            '{' => {
+                // The leading space keeps the tag off a token right before the
+                // brace, as in `{:a{:b 1}}` or `#my/tag{:x 1}`.
+                let replacement = format!(" #{} [{} ", map::EDN_MAP_TAG, start);
+                out.pop();
+                replacements.push(Replacement {
+                    at: out.len(),
+                    len: replacement.len(),
+                    original_len: 1,
+                });
+                out.push_str(&replacement);
+                open_maps.push(true);
+            }
+            '(' | '[' => open_maps.push(false),
+            '}' => {
+                if open_maps.pop() == Some(true) {
+                    out.pop();
+                    out.push(']');
+                }
+            }
+            ')' | ']' => {
+                open_maps.pop();
+            }
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    out.push(c);
+                    if c == '"' {
+                        break;
+                    } else if c == '\\' {
+                        if let Some((_, escaped)) = chars.next() {
+                            out.push(escaped);
+                        }
+                    }
+                }
+            }
+            ';' => {
+                while let Some((_, c)) = chars.next() {
+                    out.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    out.push(c);
+                }
+                while let Some(&(_, c)) = chars.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    out.push(c);
+                    chars.next();
+                }
+            }
+            _ if is_delimiter(c) => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(idx, c)) = chars.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    end = idx + c.len_utf8();
+                    chars.next();
+                }
+
+                let token = &contents[start..end];
+                out.truncate(out.len() - c.len_utf8());
+
+                if is_extended_literal(token) {
+                    let replacement = format!("#{} \"{}\"", LITERAL_TAG, token);
+                    replacements.push(Replacement {
+                        at: out.len(),
+                        len: replacement.len(),
+                        original_len: token.len(),
+                    });
+                    out.push_str(&replacement);
+                } else {
+                    out.push_str(token);
+                }
+
+                // `#{` opens a set, which is read as it is.
+                if token == "#" {
+                    if let Some(&(_, '{')) = chars.peek() {
+                        chars.next();
+                        out.push('{');
+                        open_maps.push(false);
+                    }
+                }
+            }
+        }
+    }
+
+    Rewritten {
+        text: out,
+        replacements,
+    }
+}
+
+/// Converts a JSON number, keeping integers and decimals which do not fit in
+/// an `i64`/`f64` exact. Requires serde_json's `arbitrary_precision` feature.
+crate fn from_json(n: &JsonNumber) -> Value {
+    if let Some(i) = n.as_i64() {
+        return Value::Integer(i);
+    }
+
+    let text = n.to_string();
+
+    if text.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        if let Ok(i) = BigInt::from_str(&text) {
+            return Value::BigInt(i);
+        }
+    }
+
+    match (n.as_f64(), BigDecimal::from_str(&text)) {
+        (Some(f), Ok(exact)) => {
+            if BigDecimal::from_str(&f.to_string()).ok() == Some(exact.clone()) {
+                Value::from(f)
+            } else {
+                Value::BigDecimal(exact)
+            }
+        }
+        (Some(f), Err(_)) => Value::from(f),
+        (None, Ok(exact)) => Value::BigDecimal(exact),
+        (None, Err(_)) => Value::from(std::f64::NAN),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(s: &str) -> Value {
+        parse_literal(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(lit("42"), Value::Integer(42));
+        assert_eq!(lit("-42N"), Value::BigInt(BigInt::from(-42)));
+        assert_eq!(lit("4/2"), Value::Integer(2));
+        assert_eq!(
+            lit("99999999999999999999"),
+            Value::BigInt(BigInt::from_str("99999999999999999999").unwrap())
+        );
+        assert_eq!(lit("1.50M"), Value::BigDecimal(BigDecimal::from_str("1.50").unwrap()));
+        assert_eq!(parse_literal("abc"), None);
+    }
+
+    #[test]
+    fn test_rewrite_literals() {
+        let rewritten = rewrite_literals("[1 2N \"3N\" \\N ; 4N\n 1/3 x]");
+        assert_eq!(
+            rewritten.text,
+            "[1 #eq/number \"2N\" \"3N\" \\N ; 4N\n #eq/number \"1/3\" x]"
+        );
+        assert_eq!(rewritten.original_offset(1), 1);
+        assert_eq!(rewritten.original_offset(8), 3);
+        assert_eq!(rewritten.text.find('x').map(|o| rewritten.original_offset(o)), Some(24));
+        assert_eq!(rewrite_literals("[##Inf 1.0]").text, "[#eq/number \"##Inf\" 1.0]");
+    }
+
+    #[test]
+    fn test_rewrite_maps() {
+        let rewritten = rewrite_literals("{:b #{1} :a {\"}\" \\{}} x");
+        assert_eq!(
+            rewritten.text,
+            " #eq/map [0 :b #{1} :a  #eq/map [12 \"}\" \\{]] x"
+        );
+        assert_eq!(rewritten.text.find('x').map(|o| rewritten.original_offset(o)), Some(22));
+        assert_eq!(rewritten.original_offset(0), 0);
+        assert_eq!(rewritten.original_offset(23), 12);
+
+        assert_eq!(
+            rewrite_literals("{:a{:b 1}}").text,
+            " #eq/map [0 :a #eq/map [3 :b 1]]"
+        );
+        assert_eq!(
+            rewrite_literals("#my/tag{:x 1}").text,
+            "#my/tag #eq/map [7 :x 1]"
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            apply(Operator::Add, &Value::Integer(std::i64::MAX), &Value::Integer(1)).unwrap(),
+            Value::BigInt(BigInt::from(std::i64::MAX) + BigInt::from(1))
+        );
+        assert_eq!(
+            apply(Operator::Divide, &Value::Integer(1), &Value::Integer(3)).unwrap(),
+            lit("1/3")
+        );
+        assert_eq!(
+            apply(Operator::Add, &lit("0.10M"), &lit("0.20M")).unwrap(),
+            lit("0.30M")
+        );
+        assert!(apply(Operator::Divide, &Value::Integer(1), &Value::Integer(0)).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare(&lit("1"), &lit("1.0")), Some(Ordering::Equal));
+        assert_eq!(compare(&lit("1/3"), &lit("0.5M")), Some(Ordering::Less));
+        assert_eq!(compare(&lit("99999999999999999999"), &lit("1")), Some(Ordering::Greater));
+        assert_eq!(compare(&lit("1"), &Value::Nil), None);
+    }
+}
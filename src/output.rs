@@ -1,17 +1,28 @@
-use colored::*;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeSet;
 use std::io;
 use std::io::Write;
+use std::iter::once;
 use std::str;
 
+use bigdecimal::BigDecimal;
 use clap::{_clap_count_exprs, arg_enum};
-use edn::Value as EdnValue;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use serde_json;
+use serde_json::Map as JsonMap;
 use serde_json::Value as JsonValue;
 
+use super::diff::{Change, DiffFormat};
+use super::map::Map;
+use super::theme::{ColorTheme, Painted, Style};
+use super::transit;
+use super::value::{canonical_entries, canonical_items, Value as EdnValue};
+
 arg_enum! {
     pub enum OutputFormat {
         EDN,
         JSON,
+        Transit,
     }
 }
 
@@ -30,36 +41,133 @@ crate enum OutputDestination {
 crate struct OutputOptions {
     crate format: OutputFormat,
     crate style: OutputStyle,
+    /// Target line width for the pretty printer.
+    crate width: usize,
+    crate theme: ColorTheme,
+    /// Color brackets by nesting depth using the theme's rainbow palette.
+    crate rainbow: bool,
+    /// Write EDN in canonical form: compact, uncolored, one form per line,
+    /// with maps and sets in canonical order. JSON output sorts its keys.
+    crate canonical: bool,
+    /// Sort the keys of JSON objects.
+    crate sort_keys: bool,
+    /// Write top-level strings, keywords and symbols as bare text.
+    crate raw: bool,
+    /// Written after every top-level form.
+    crate separator: String,
+    /// Wrap all the forms in a single vector.
+    crate slurp_output: bool,
     crate destination: OutputDestination,
 }
 
-crate struct ColorTheme {
-    nil: Color,
-    boolean: Color,
-    keyword: Color,
-    char: Color,
-    string: Color,
-    number: Color,
-    tag: Color,
-    symbol: Color,
-    vector: Color,
-    list: Color,
-    map: Color,
-}
-
-static DEFAULT_THEME: ColorTheme = ColorTheme {
-    nil: Color::BrightBlue,
-    symbol: Color::Cyan,
-    boolean: Color::Magenta,
-    char: Color::BrightRed,
-    string: Color::Yellow,
-    number: Color::Green,
-    keyword: Color::Red,
-    tag: Color::BrightGreen,
-    vector: Color::BrightYellow,
-    list: Color::BrightYellow,
-    map: Color::White,
-};
+/// Escapes a string's contents for use between double quotes in EDN.
+fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            _ if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The EDN literal for a character, using the named forms for whitespace.
+fn char_literal(value: char) -> String {
+    match value {
+        '\n' => "\\newline".to_owned(),
+        ' ' => "\\space".to_owned(),
+        '\t' => "\\tab".to_owned(),
+        '\r' => "\\return".to_owned(),
+        '\u{8}' => "\\backspace".to_owned(),
+        '\u{c}' => "\\formfeed".to_owned(),
+        _ if value.is_control() || value.is_whitespace() => {
+            format!("\\u{:04x}", value as u32)
+        }
+        _ => format!("\\{}", value),
+    }
+}
+
+/// The EDN literal for a float. Whole numbers keep a decimal point so that
+/// they read back as floats, and NaN and the infinities use the symbolic
+/// `##` forms.
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "##NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "##Inf" } else { "##-Inf" }.to_owned()
+    } else {
+        let literal = value.to_string();
+        if literal.contains(|c| c == '.' || c == 'e' || c == 'E') {
+            literal
+        } else {
+            literal + ".0"
+        }
+    }
+}
+
+/// The width of a value written on a single line, or `None` as soon as it is
+/// known to exceed `limit`.
+fn flat_width(value: &EdnValue, limit: usize) -> Option<usize> {
+    let width = match value {
+        EdnValue::Nil => 3,
+        EdnValue::Boolean(b) => if *b { 4 } else { 5 },
+        EdnValue::String(s) => escape_string(s).chars().count() + 2,
+        EdnValue::Char(c) => char_literal(*c).chars().count(),
+        EdnValue::Symbol(s) => s.chars().count(),
+        EdnValue::Keyword(k) => k.chars().count() + 1,
+        EdnValue::Integer(i) => i.to_string().len(),
+        EdnValue::BigInt(i) => i.to_string().len() + 1,
+        EdnValue::Float(f) => float_literal(f.into_inner()).len(),
+        EdnValue::BigDecimal(d) => d.to_string().len() + 1,
+        EdnValue::Ratio(r) => r.to_string().len(),
+        EdnValue::List(items) | EdnValue::Vector(items) => flat_width_items(items.iter(), 2, limit)?,
+        EdnValue::Set(items) => flat_width_items(items.iter(), 3, limit)?,
+        EdnValue::Map(m) => {
+            flat_width_items(m.iter().flat_map(|(k, v)| once(k).chain(once(v))), 2, limit)?
+        }
+        EdnValue::Tagged(tag, inner) => {
+            let prefix = tag.chars().count() + 2;
+            prefix + flat_width(inner, limit.saturating_sub(prefix))?
+        }
+    };
+
+    if width <= limit {
+        Some(width)
+    } else {
+        None
+    }
+}
+
+/// The single-line width of a collection's items separated by spaces, plus
+/// `delimiters` columns for its brackets.
+fn flat_width_items(
+    items: impl Iterator<Item = &'a EdnValue>,
+    delimiters: usize,
+    limit: usize,
+) -> Option<usize> {
+    let mut width = delimiters;
+
+    for (idx, item) in items.enumerate() {
+        if idx > 0 {
+            width += 1;
+        }
+        width += flat_width(item, limit.checked_sub(width)?)?;
+    }
+
+    if width <= limit {
+        Some(width)
+    } else {
+        None
+    }
+}
 
 trait EdnFormatter {
     fn write_nil(&mut self) -> io::Result<()>;
@@ -68,11 +176,14 @@ trait EdnFormatter {
     fn write_symbol(&mut self, value: String) -> io::Result<()>;
     fn write_float(&mut self, value: f64) -> io::Result<()>;
     fn write_integer(&mut self, value: i64) -> io::Result<()>;
+    fn write_bigint(&mut self, value: BigInt) -> io::Result<()>;
+    fn write_bigdecimal(&mut self, value: BigDecimal) -> io::Result<()>;
+    fn write_ratio(&mut self, value: BigRational) -> io::Result<()>;
     fn write_string(&mut self, value: String) -> io::Result<()>;
     fn write_keyword(&mut self, value: String) -> io::Result<()>;
     fn write_list(&mut self, value: Vec<EdnValue>) -> io::Result<()>;
     fn write_vector(&mut self, value: Vec<EdnValue>) -> io::Result<()>;
-    fn write_map(&mut self, value: BTreeMap<EdnValue, EdnValue>) -> io::Result<()>;
+    fn write_map(&mut self, value: Map) -> io::Result<()>;
     fn write_set(&mut self, value: BTreeSet<EdnValue>) -> io::Result<()>;
     fn write_tagged(&mut self, x: String, y: Box<EdnValue>) -> io::Result<()>;
 
@@ -106,7 +217,10 @@ trait EdnFormatter {
             EdnValue::Symbol(s) => self.write_symbol(s),
             EdnValue::Keyword(k) => self.write_keyword(k),
             EdnValue::Integer(i) => self.write_integer(i),
-            EdnValue::Float(f) => self.write_float(f.into()),
+            EdnValue::BigInt(i) => self.write_bigint(i),
+            EdnValue::Float(f) => self.write_float(f.into_inner()),
+            EdnValue::BigDecimal(d) => self.write_bigdecimal(d),
+            EdnValue::Ratio(r) => self.write_ratio(r),
             EdnValue::List(l) => self.write_list(l),
             EdnValue::Vector(v) => self.write_vector(v),
             EdnValue::Map(m) => self.write_map(m),
@@ -114,28 +228,56 @@ trait EdnFormatter {
             EdnValue::Tagged(x, y) => self.write_tagged(x, y),
         }
     }
+}
+
+struct CompactEdnFormatter<W> {
+    depth: usize,
+    theme: ColorTheme,
+    rainbow: bool,
+    canonical: bool,
+    writer: W,
+}
 
-    fn reset(&mut self) {
-        ()
+impl<W: Write> CompactEdnFormatter<W> {
+    fn new(writer: W, theme: ColorTheme, rainbow: bool) -> CompactEdnFormatter<W> {
+        CompactEdnFormatter {
+            depth: 0,
+            theme,
+            rainbow,
+            canonical: false,
+            writer,
+        }
     }
 
-    fn write_forms(&mut self, forms: Vec<EdnValue>) -> io::Result<()> {
-        for form in forms {
-            try!(self.write_form(form));
-            self.reset();
+    /// A formatter for canonical output, which never colors and writes maps
+    /// and sets in canonical order.
+    fn canonical(writer: W) -> CompactEdnFormatter<W> {
+        CompactEdnFormatter {
+            canonical: true,
+            ..CompactEdnFormatter::new(writer, ColorTheme::plain(), false)
         }
+    }
 
-        Ok(())
+    /// Writes a collection bracket, in the rainbow color for the current
+    /// depth when enabled.
+    fn write_bracket(&mut self, style: Style, bracket: &str) -> io::Result<()> {
+        let style = if self.rainbow {
+            self.theme.rainbow_at(self.depth).unwrap_or(style)
+        } else {
+            style
+        };
+        write!(self.writer, "{}", style.paint(bracket))
     }
-}
 
-struct CompactEdnFormatter<W> {
-    writer: W,
-}
+    fn open(&mut self, style: Style, bracket: &str) -> io::Result<()> {
+        try!(self.write_bracket(style, bracket));
+        self.depth += 1;
+        Ok(())
+    }
 
-impl<W: Write> CompactEdnFormatter<W> {
-    fn new(writer: W) -> CompactEdnFormatter<W> {
-        CompactEdnFormatter { writer }
+    fn close(&mut self, style: Style, bracket: &str) -> io::Result<()> {
+        self.depth -= 1;
+        self.write_bracket(style, bracket)
     }
 }
 
@@ -151,45 +293,51 @@ impl<W: Write> Write for CompactEdnFormatter<W> {
 
 impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     fn write_nil(&mut self) -> io::Result<()> {
-        write!(self, "{}", "nil".color(DEFAULT_THEME.nil))
+        write!(self.writer, "{}", self.theme.nil.paint("nil"))
     }
 
     fn write_boolean(&mut self, value: bool) -> io::Result<()> {
         let as_str = if value { "true" } else { "false" };
-        write!(self, "{}", as_str.color(DEFAULT_THEME.boolean))
+        write!(self.writer, "{}", self.theme.boolean.paint(as_str))
     }
 
     fn write_char(&mut self, value: char) -> io::Result<()> {
-        try!(write!(self, "{}", "\\".color(DEFAULT_THEME.char)));
-        try!(write!(
-            self.writer,
-            "{}",
-            value.encode_utf8(&mut [0; 4]).color(DEFAULT_THEME.char)
-        ));
-        Ok(())
+        write!(self.writer, "{}", self.theme.char.paint(char_literal(value)))
     }
 
     fn write_symbol(&mut self, value: String) -> io::Result<()> {
-        write!(self, "{}", value.color(DEFAULT_THEME.symbol))
+        write!(self.writer, "{}", self.theme.symbol.paint(value))
     }
 
     fn write_float(&mut self, value: f64) -> io::Result<()> {
-        write!(self, "{}", value.to_string().color(DEFAULT_THEME.number))
+        write!(self.writer, "{}", self.theme.number.paint(float_literal(value)))
     }
 
     fn write_integer(&mut self, value: i64) -> io::Result<()> {
-        write!(self, "{}", value.to_string().color(DEFAULT_THEME.number))
+        write!(self.writer, "{}", self.theme.number.paint(value.to_string()))
+    }
+
+    fn write_bigint(&mut self, value: BigInt) -> io::Result<()> {
+        write!(self.writer, "{}", self.theme.number.paint(format!("{}N", value)))
+    }
+
+    fn write_bigdecimal(&mut self, value: BigDecimal) -> io::Result<()> {
+        write!(self.writer, "{}", self.theme.number.paint(format!("{}M", value)))
+    }
+
+    fn write_ratio(&mut self, value: BigRational) -> io::Result<()> {
+        write!(self.writer, "{}", self.theme.number.paint(value.to_string()))
     }
 
     fn write_string(&mut self, value: String) -> io::Result<()> {
         try!(self.begin_string());
-        try!(write!(self, "{}", value.color(DEFAULT_THEME.string)));
+        try!(write!(self.writer, "{}", self.theme.string.paint(escape_string(&value))));
         self.end_string()
     }
 
     fn write_keyword(&mut self, value: String) -> io::Result<()> {
-        try!(write!(self, "{}", ":".color(DEFAULT_THEME.keyword)));
-        try!(write!(self, "{}", value.color(DEFAULT_THEME.keyword)));
+        try!(write!(self.writer, "{}", self.theme.keyword.paint(":")));
+        try!(write!(self.writer, "{}", self.theme.keyword.paint(value)));
         Ok(())
     }
 
@@ -220,11 +368,13 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn begin_vector(&mut self) -> io::Result<()> {
-        write!(self, "{}", "[".color(DEFAULT_THEME.vector))
+        let style = self.theme.vector;
+        self.open(style, "[")
     }
 
     fn end_vector(&mut self) -> io::Result<()> {
-        write!(self, "{}", "]".color(DEFAULT_THEME.vector))
+        let style = self.theme.vector;
+        self.close(style, "]")
     }
 
     fn begin_vector_item(&mut self, first: bool) -> io::Result<()> {
@@ -239,11 +389,13 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn begin_list(&mut self) -> io::Result<()> {
-        write!(self, "{}", "(".color(DEFAULT_THEME.list))
+        let style = self.theme.list;
+        self.open(style, "(")
     }
 
     fn end_list(&mut self) -> io::Result<()> {
-        write!(self, "{}", ")".color(DEFAULT_THEME.list))
+        let style = self.theme.list;
+        self.close(style, ")")
     }
 
     fn begin_list_item(&mut self, first: bool) -> io::Result<()> {
@@ -258,19 +410,21 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn begin_string(&mut self) -> io::Result<()> {
-        self.writer.write_all(b"\"")
+        write!(self.writer, "{}", self.theme.string.paint("\""))
     }
 
     fn end_string(&mut self) -> io::Result<()> {
-        self.writer.write_all(b"\"")
+        write!(self.writer, "{}", self.theme.string.paint("\""))
     }
 
     fn begin_map(&mut self) -> io::Result<()> {
-        self.writer.write_all(b"{")
+        let style = self.theme.map;
+        self.open(style, "{")
     }
 
     fn end_map(&mut self) -> io::Result<()> {
-        self.writer.write_all(b"}")
+        let style = self.theme.map;
+        self.close(style, "}")
     }
 
     fn begin_map_key(&mut self, first: bool) -> io::Result<()> {
@@ -293,9 +447,18 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
         Ok(())
     }
 
-    fn write_map(&mut self, value: BTreeMap<EdnValue, EdnValue>) -> io::Result<()> {
+    fn write_map(&mut self, value: Map) -> io::Result<()> {
+        let entries: Vec<(EdnValue, EdnValue)> = if self.canonical {
+            canonical_entries(&value)
+                .into_iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        } else {
+            value.into_iter().collect()
+        };
+
         try!(self.begin_map());
-        for (idx, (k, v)) in value.into_iter().enumerate() {
+        for (idx, (k, v)) in entries.into_iter().enumerate() {
             try!(self.begin_map_key(idx == 0));
             try!(self.write_form(k));
             try!(self.end_map_key(idx == 0));
@@ -309,13 +472,13 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn begin_set(&mut self) -> io::Result<()> {
-        try!(self.writer.write_all(b"#{"));
-        Ok(())
+        let style = self.theme.set;
+        self.open(style, "#{")
     }
 
     fn end_set(&mut self) -> io::Result<()> {
-        try!(self.writer.write_all(b"}"));
-        Ok(())
+        let style = self.theme.set;
+        self.close(style, "}")
     }
 
     fn begin_set_item(&mut self, first: bool) -> io::Result<()> {
@@ -330,8 +493,14 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn write_set(&mut self, value: BTreeSet<EdnValue>) -> io::Result<()> {
+        let items: Vec<EdnValue> = if self.canonical {
+            canonical_items(&value).into_iter().cloned().collect()
+        } else {
+            value.into_iter().collect()
+        };
+
         try!(self.begin_set());
-        for (idx, item) in value.into_iter().enumerate() {
+        for (idx, item) in items.into_iter().enumerate() {
             try!(self.begin_set_item(idx == 0));
             try!(self.write_form(item));
             try!(self.end_set_item());
@@ -341,72 +510,271 @@ impl<W: Write> EdnFormatter for CompactEdnFormatter<W> {
     }
 
     fn write_tagged(&mut self, x: String, y: Box<EdnValue>) -> io::Result<()> {
-        try!(write!(self, "{}", "#".color(DEFAULT_THEME.tag)));
-        try!(write!(self, "{}", x.color(DEFAULT_THEME.tag)));
-        try!(write!(self, "{}", " ".color(DEFAULT_THEME.tag)));
+        try!(write!(self.writer, "{}", self.theme.tag.paint("#")));
+        try!(write!(self.writer, "{}", self.theme.tag.paint(x)));
+        try!(write!(self.writer, "{}", self.theme.tag.paint(" ")));
         try!(self.write_form(*y));
 
         Ok(())
     }
 }
 
-trait JsonFormatter {
-    /*fn write_null(&mut self) -> io::Result<()> {
-        write!(self, "{}", "nil".color(DEFAULT_THEME.nil))
+/// Writes a value as compact, uncolored EDN.
+crate fn edn_string(value: EdnValue) -> String {
+    let mut buf: Vec<u8> = vec![];
+    CompactEdnFormatter::new(&mut buf, ColorTheme::plain(), false)
+        .write_form(value)
+        .expect("writing to a Vec can not fail");
+    String::from_utf8(buf).expect("EDN output is UTF-8")
+}
+
+/// The JSON object key for a map key: strings as-is, keywords by name, and
+/// anything else as its EDN text.
+fn json_key(key: EdnValue) -> String {
+    match key {
+        EdnValue::String(s) | EdnValue::Keyword(s) => s,
+        other => edn_string(other),
     }
+}
 
-    fn write_undefined(&mut self) -> io::Result<()> {
-        write!(self, "{}", "nil".color(DEFAULT_THEME.nil))
-    }*/
+/// Parses the text of a number into a JSON number, relying on serde_json's
+/// `arbitrary_precision` to keep every digit.
+fn json_number(text: String) -> JsonValue {
+    serde_json::from_str(&text).unwrap_or(JsonValue::String(text))
+}
+
+/// Converts a value to JSON. Keywords, symbols and characters become strings,
+/// lists and sets become arrays, tags are dropped in favor of the tagged value
+/// and ratios are written as decimals. Fails when two keys of a map, such as
+/// `"a"` and `:a`, become the same JSON key.
+fn edn_to_json(value: EdnValue) -> io::Result<JsonValue> {
+    let json = match value {
+        EdnValue::Nil => JsonValue::Null,
+        EdnValue::Boolean(b) => JsonValue::Bool(b),
+        EdnValue::String(s) | EdnValue::Symbol(s) | EdnValue::Keyword(s) => {
+            JsonValue::String(s)
+        }
+        EdnValue::Char(c) => JsonValue::String(c.to_string()),
+        EdnValue::Integer(i) => JsonValue::from(i),
+        EdnValue::BigInt(i) => json_number(i.to_string()),
+        EdnValue::Float(f) => serde_json::Number::from_f64(f.into_inner())
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        EdnValue::BigDecimal(d) => json_number(d.to_string()),
+        EdnValue::Ratio(r) => {
+            let numer = BigDecimal::new(r.numer().clone(), 0);
+            json_number((numer / BigDecimal::new(r.denom().clone(), 0)).to_string())
+        }
+        EdnValue::List(items) | EdnValue::Vector(items) => {
+            JsonValue::Array(try!(items.into_iter().map(edn_to_json).collect::<io::Result<_>>()))
+        }
+        EdnValue::Set(items) => {
+            JsonValue::Array(try!(items.into_iter().map(edn_to_json).collect::<io::Result<_>>()))
+        }
+        EdnValue::Map(m) => {
+            let mut object: JsonMap<String, JsonValue> = JsonMap::new();
+
+            for (k, v) in m {
+                let key = json_key(k);
+
+                if object.contains_key(&key) {
+                    let message = format!(
+                        "more than one map key becomes the JSON key {}",
+                        JsonValue::from(key)
+                    );
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+
+                object.insert(key, try!(edn_to_json(v)));
+            }
+
+            JsonValue::Object(object)
+        }
+        EdnValue::Tagged(_, inner) => try!(edn_to_json(*inner)),
+    };
+
+    Ok(json)
+}
+
+/// Writes JSON using the same theme as EDN output. Pretty output indents by
+/// two spaces per level; compact output has no whitespace at all.
+struct JsonFormatter<W> {
+    pretty: bool,
+    sort_keys: bool,
+    depth: usize,
+    theme: ColorTheme,
+    writer: W,
+}
+
+impl<W: Write> JsonFormatter<W> {
+    fn new(writer: W, pretty: bool, sort_keys: bool, theme: ColorTheme) -> JsonFormatter<W> {
+        JsonFormatter {
+            pretty,
+            sort_keys,
+            depth: 0,
+            theme,
+            writer,
+        }
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        if self.pretty {
+            try!(write!(self.writer, "\n{}", "  ".repeat(self.depth)));
+        }
+        Ok(())
+    }
+
+    fn write_string(&mut self, style: Style, s: &str) -> io::Result<()> {
+        let quoted = serde_json::to_string(s).expect("strings always serialize");
+        write!(self.writer, "{}", style.paint(quoted))
+    }
+
+    fn write_value(&mut self, value: &JsonValue) -> io::Result<()> {
+        match value {
+            JsonValue::Null => write!(self.writer, "{}", self.theme.nil.paint("null")),
+            JsonValue::Bool(b) => {
+                write!(self.writer, "{}", self.theme.boolean.paint(b.to_string()))
+            }
+            JsonValue::Number(n) => {
+                write!(self.writer, "{}", self.theme.number.paint(n.to_string()))
+            }
+            JsonValue::String(s) => {
+                let style = self.theme.string;
+                self.write_string(style, s)
+            }
+            JsonValue::Array(items) => {
+                if items.is_empty() {
+                    return write!(self.writer, "{}", self.theme.vector.paint("[]"));
+                }
+
+                try!(write!(self.writer, "{}", self.theme.vector.paint("[")));
+                self.depth += 1;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        try!(self.writer.write_all(b","));
+                    }
+                    try!(self.newline());
+                    try!(self.write_value(item));
+                }
+                self.depth -= 1;
+                try!(self.newline());
+                write!(self.writer, "{}", self.theme.vector.paint("]"))
+            }
+            JsonValue::Object(entries) => {
+                if entries.is_empty() {
+                    return write!(self.writer, "{}", self.theme.map.paint("{}"));
+                }
+
+                let mut entries: Vec<(&String, &JsonValue)> = entries.iter().collect();
+                if self.sort_keys {
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                }
+
+                try!(write!(self.writer, "{}", self.theme.map.paint("{")));
+                self.depth += 1;
+                for (idx, (key, item)) in entries.into_iter().enumerate() {
+                    if idx > 0 {
+                        try!(self.writer.write_all(b","));
+                    }
+                    try!(self.newline());
+
+                    let style = self.theme.keyword;
+                    try!(self.write_string(style, key));
+                    try!(self.writer.write_all(if self.pretty { b": " } else { b":" }));
+                    try!(self.write_value(item));
+                }
+                self.depth -= 1;
+                try!(self.newline());
+                write!(self.writer, "{}", self.theme.map.paint("}"))
+            }
+        }
+    }
+
+    fn write_form(&mut self, form: EdnValue) -> io::Result<()> {
+        let json = try!(edn_to_json(form));
+        self.write_value(&json)
+    }
 }
 
 crate struct PrettyEdnFormatter<W: Write> {
     current_column: usize,
     offsets: Vec<usize>,
+    /// Parallel to `offsets`: whether each open collection is laid out on a
+    /// single line.
+    inline: Vec<bool>,
     has_value: bool,
+    width: usize,
+    theme: ColorTheme,
+    rainbow: bool,
     writer: W,
 }
 
 impl<W: Write> PrettyEdnFormatter<W> {
-    fn new(writer: W) -> Self {
+    fn new(writer: W, width: usize, theme: ColorTheme, rainbow: bool) -> Self {
         PrettyEdnFormatter {
             current_column: 0,
             offsets: vec![],
+            inline: vec![],
             has_value: false,
+            width,
+            theme,
+            rainbow,
             writer,
         }
     }
 
-    fn write(&mut self, s: ColoredString) -> io::Result<()> {
-        for mut c in s.chars() {
-            //println!("{:?}", '\u{001b}' as usize);
-            //println!("{:?}", c as usize);
+    /// Writes a collection bracket. Opening brackets are written before their
+    /// offset is pushed and closing ones after it is popped, so the depth of
+    /// `offsets` is the same for both.
+    fn write_bracket(&mut self, style: Style, bracket: &str) -> io::Result<()> {
+        let style = if self.rainbow {
+            self.theme.rainbow_at(self.offsets.len()).unwrap_or(style)
+        } else {
+            style
+        };
+        self.write(style.paint(bracket))
+    }
+
+    fn write(&mut self, s: Painted) -> io::Result<()> {
+        for c in s.text().chars() {
             match c {
                 '\n' => self.current_column = 0,
-                '\t' => {
-                    c = ' ';
-                    self.current_column += 1
-                }
-                '\u{001b}' => {}
                 _ if c.is_control() => {}
                 _ => self.current_column += 1,
             }
-
-            try!(self.writer.write_all(c.encode_utf8(&mut [0; 4]).as_bytes()));
         }
 
-        Ok(())
+        write!(self.writer, "{}", s)
     }
 
     fn flush(&mut self) -> io::Result<()> {
         self.writer.flush()
     }
 
+    /// Columns left on the current line, keeping room for the closing
+    /// delimiters of every open collection.
+    fn remaining(&self) -> usize {
+        self.width
+            .saturating_sub(self.current_column + self.offsets.len())
+    }
+
+    /// Writes the separator between two items of the innermost collection:
+    /// a space when it fits on one line, otherwise a newline aligned with its
+    /// first item.
+    fn separate(&mut self) -> io::Result<()> {
+        if self.inline.last() == Some(&true) {
+            self.write(Painted::plain(" "))
+        } else {
+            try!(self.write(Painted::plain("\n")));
+            self.indent()
+        }
+    }
+
     fn indent(&mut self) -> io::Result<()> {
         match self.offsets.last() {
             Some(&n) => {
                 for _ in 0..n {
-                    try!(self.write(ColoredString::from(" ")));
+                    try!(self.write(Painted::plain(" ")));
                 }
             }
             None => {}
@@ -417,60 +785,67 @@ impl<W: Write> PrettyEdnFormatter<W> {
 }
 
 impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
-    fn reset(&mut self) {
-        self.current_column = 0;
-        self.offsets = vec![];
-    }
-
     fn write_nil(&mut self) -> io::Result<()> {
-        self.write("nil".color(DEFAULT_THEME.nil))
+        self.write(self.theme.nil.paint("nil"))
     }
 
     fn write_boolean(&mut self, value: bool) -> io::Result<()> {
         let as_str = if value { "true" } else { "false" };
-        self.write(as_str.color(DEFAULT_THEME.boolean))
+        self.write(self.theme.boolean.paint(as_str))
     }
 
     fn write_char(&mut self, value: char) -> io::Result<()> {
-        try!(self.write("\\".color(DEFAULT_THEME.char)));
-        try!(self.write(value.encode_utf8(&mut [0; 4]).color(DEFAULT_THEME.char)));
-        Ok(())
+        self.write(self.theme.char.paint(char_literal(value)))
     }
 
     fn write_symbol(&mut self, value: String) -> io::Result<()> {
-        self.write(value.color(DEFAULT_THEME.symbol))
+        self.write(self.theme.symbol.paint(value))
     }
 
     fn write_float(&mut self, value: f64) -> io::Result<()> {
-        self.write(value.to_string().color(DEFAULT_THEME.number))
+        self.write(self.theme.number.paint(float_literal(value)))
     }
 
     fn write_integer(&mut self, value: i64) -> io::Result<()> {
-        self.write(value.to_string().color(DEFAULT_THEME.number))
+        self.write(self.theme.number.paint(value.to_string()))
+    }
+
+    fn write_bigint(&mut self, value: BigInt) -> io::Result<()> {
+        self.write(self.theme.number.paint(format!("{}N", value)))
+    }
+
+    fn write_bigdecimal(&mut self, value: BigDecimal) -> io::Result<()> {
+        self.write(self.theme.number.paint(format!("{}M", value)))
+    }
+
+    fn write_ratio(&mut self, value: BigRational) -> io::Result<()> {
+        self.write(self.theme.number.paint(value.to_string()))
     }
 
     fn write_string(&mut self, value: String) -> io::Result<()> {
         try!(self.begin_string());
-        try!(self.write(value.color(DEFAULT_THEME.string)));
+        try!(self.write(self.theme.string.paint(escape_string(&value))));
         self.end_string()
     }
 
     fn begin_string(&mut self) -> io::Result<()> {
-        self.write(ColoredString::from("\""))
+        self.write(self.theme.string.paint("\""))
     }
 
     fn end_string(&mut self) -> io::Result<()> {
-        self.write(ColoredString::from("\""))
+        self.write(self.theme.string.paint("\""))
     }
 
     fn write_keyword(&mut self, value: String) -> io::Result<()> {
-        try!(self.write(":".color(DEFAULT_THEME.keyword)));
-        try!(self.write(value.color(DEFAULT_THEME.keyword)));
+        try!(self.write(self.theme.keyword.paint(":")));
+        try!(self.write(self.theme.keyword.paint(value)));
         Ok(())
     }
 
     fn write_vector(&mut self, value: Vec<EdnValue>) -> io::Result<()> {
+        let inline = flat_width_items(value.iter(), 2, self.remaining()).is_some();
         try!(self.begin_vector());
+        self.inline.push(inline);
 
         for (idx, item) in value.into_iter().enumerate() {
             try!(self.begin_vector_item(idx == 0));
@@ -483,7 +858,9 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn write_list(&mut self, value: Vec<EdnValue>) -> io::Result<()> {
+        let inline = flat_width_items(value.iter(), 2, self.remaining()).is_some();
         try!(self.begin_list());
+        self.inline.push(inline);
 
         for (idx, item) in value.into_iter().enumerate() {
             try!(self.begin_list_item(idx == 0));
@@ -496,7 +873,8 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn begin_vector(&mut self) -> io::Result<()> {
-        try!(self.write(ColoredString::from("[")));
+        let style = self.theme.vector;
+        try!(self.write_bracket(style, "["));
 
         self.has_value = false;
         self.offsets.push(self.current_column);
@@ -506,13 +884,14 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
 
     fn end_vector(&mut self) -> io::Result<()> {
         self.offsets.pop();
-        self.write(ColoredString::from("]"))
+        self.inline.pop();
+        let style = self.theme.vector;
+        self.write_bracket(style, "]")
     }
 
     fn begin_vector_item(&mut self, first: bool) -> io::Result<()> {
         if !first {
-            try!(self.write(ColoredString::from("\n")));
-            try!(self.indent());
+            try!(self.separate());
         }
 
         Ok(())
@@ -524,7 +903,8 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn begin_map(&mut self) -> io::Result<()> {
-        try!(self.write(ColoredString::from("{")));
+        let style = self.theme.map;
+        try!(self.write_bracket(style, "{"));
         self.offsets.push(self.current_column);
         self.has_value = false;
         Ok(())
@@ -532,20 +912,22 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
 
     fn end_map(&mut self) -> io::Result<()> {
         self.offsets.pop();
-        self.write(ColoredString::from("}"))
+        self.inline.pop();
+        let style = self.theme.map;
+        self.write_bracket(style, "}")
     }
 
     fn begin_map_key(&mut self, first: bool) -> io::Result<()> {
         if !first {
-            try!(self.write(ColoredString::from("\n")));
-            try!(self.indent());
+            try!(self.separate());
         }
 
         Ok(())
     }
 
     fn begin_list(&mut self) -> io::Result<()> {
-        try!(self.write(ColoredString::from("(")));
+        let style = self.theme.list;
+        try!(self.write_bracket(style, "("));
         self.has_value = false;
         self.offsets.push(self.current_column);
         Ok(())
@@ -553,14 +935,15 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
 
     fn end_list(&mut self) -> io::Result<()> {
         self.offsets.pop();
+        self.inline.pop();
 
-        self.write(ColoredString::from(")"))
+        let style = self.theme.list;
+        self.write_bracket(style, ")")
     }
 
     fn begin_list_item(&mut self, first: bool) -> io::Result<()> {
         if !first {
-            try!(self.write(ColoredString::from("\n")));
-            try!(self.indent());
+            try!(self.separate());
         }
 
         Ok(())
@@ -576,15 +959,18 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn begin_map_value(&mut self) -> io::Result<()> {
-        self.write(ColoredString::from(" "))
+        self.write(Painted::plain(" "))
     }
 
     fn end_map_value(&mut self) -> io::Result<()> {
         Ok(())
     }
 
-    fn write_map(&mut self, value: BTreeMap<EdnValue, EdnValue>) -> io::Result<()> {
+    fn write_map(&mut self, value: Map) -> io::Result<()> {
+        let entries = value.iter().flat_map(|(k, v)| once(k).chain(once(v)));
+        let inline = flat_width_items(entries, 2, self.remaining()).is_some();
         try!(self.begin_map());
+        self.inline.push(inline);
         for (idx, (k, v)) in value.into_iter().enumerate() {
             try!(self.begin_map_key(idx == 0));
             try!(self.write_form(k));
@@ -599,20 +985,22 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn begin_set(&mut self) -> io::Result<()> {
-        try!(self.write(ColoredString::from("#{")));
+        let style = self.theme.set;
+        try!(self.write_bracket(style, "#{"));
         self.offsets.push(self.current_column);
         Ok(())
     }
 
     fn end_set(&mut self) -> io::Result<()> {
         self.offsets.pop();
-        self.write(ColoredString::from("}"))
+        self.inline.pop();
+        let style = self.theme.set;
+        self.write_bracket(style, "}")
     }
 
     fn begin_set_item(&mut self, first: bool) -> io::Result<()> {
         if !first {
-        try!(self.write(ColoredString::from("\n")));
-            try!(self.indent());
+            try!(self.separate());
         }
         Ok(())
     }
@@ -622,7 +1010,9 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn write_set(&mut self, value: BTreeSet<EdnValue>) -> io::Result<()> {
+        let inline = flat_width_items(value.iter(), 3, self.remaining()).is_some();
         try!(self.begin_set());
+        self.inline.push(inline);
         for (idx, item) in value.into_iter().enumerate() {
             try!(self.begin_set_item(idx == 0));
             try!(self.write_form(item));
@@ -633,45 +1023,214 @@ impl<W: Write> EdnFormatter for PrettyEdnFormatter<W> {
     }
 
     fn write_tagged(&mut self, x: String, y: Box<EdnValue>) -> io::Result<()> {
-        try!(self.write("#".color(DEFAULT_THEME.tag)));
-        try!(self.write(x.color(DEFAULT_THEME.tag)));
-        try!(self.write(" ".color(DEFAULT_THEME.tag)));
+        try!(self.write(self.theme.tag.paint("#")));
+        try!(self.write(self.theme.tag.paint(x)));
+        try!(self.write(self.theme.tag.paint(" ")));
         try!(self.write_form(*y));
 
         Ok(())
     }
 }
 
-crate fn format_output(forms: Vec<EdnValue>, opts: &OutputOptions) -> io::Result<()> {
-    let writer = match &opts.destination {
-        OutputDestination::Stdout => io::stdout(),
-        OutputDestination::File(_path) => io::stdout(),
-    };
+fn write_transit<W: Write>(mut writer: W, form: EdnValue, pretty: bool) -> io::Result<()> {
+    let encoded = transit::encode(form);
+
+    if pretty {
+        try!(serde_json::to_writer_pretty(&mut writer, &encoded));
+    } else {
+        try!(serde_json::to_writer(&mut writer, &encoded));
+    }
+
+    Ok(())
+}
+
+/// Writes a single top-level form in the configured format and style.
+fn write_form<W: Write>(writer: W, form: EdnValue, opts: &OutputOptions) -> io::Result<()> {
+    let theme = opts.theme.clone();
 
     match (&opts.format, &opts.style) {
+        (OutputFormat::EDN, _) if opts.canonical => {
+            CompactEdnFormatter::canonical(writer).write_form(form)
+        }
+        (OutputFormat::JSON, _) if opts.canonical => {
+            JsonFormatter::new(writer, false, true, ColorTheme::plain()).write_form(form)
+        }
         (OutputFormat::EDN, OutputStyle::Compact) => {
-            try!(CompactEdnFormatter::new(writer).write_forms(forms))
+            CompactEdnFormatter::new(writer, theme, opts.rainbow).write_form(form)
         }
         (OutputFormat::EDN, OutputStyle::Pretty) => {
-            try!(PrettyEdnFormatter::new(writer).write_forms(forms))
+            PrettyEdnFormatter::new(writer, opts.width, theme, opts.rainbow).write_form(form)
         }
         (OutputFormat::JSON, OutputStyle::Compact) => {
-            try!(CompactEdnFormatter::new(writer).write_forms(forms))
+            JsonFormatter::new(writer, false, opts.sort_keys, theme).write_form(form)
         }
         (OutputFormat::JSON, OutputStyle::Pretty) => {
-            try!(PrettyEdnFormatter::new(writer).write_forms(forms))
+            JsonFormatter::new(writer, true, opts.sort_keys, theme).write_form(form)
         }
+        (OutputFormat::Transit, OutputStyle::Compact) => write_transit(writer, form, false),
+        (OutputFormat::Transit, OutputStyle::Pretty) => write_transit(writer, form, true),
+    }
+}
+
+/// In raw mode, the bare text of a top-level string, keyword or symbol.
+fn raw_text(form: &EdnValue) -> Option<&str> {
+    match form {
+        EdnValue::String(s) | EdnValue::Keyword(s) | EdnValue::Symbol(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn write_output<W: Write>(
+    mut writer: W,
+    forms: Vec<EdnValue>,
+    opts: &OutputOptions,
+) -> io::Result<()> {
+    let forms = if opts.slurp_output {
+        vec![EdnValue::Vector(forms)]
+    } else {
+        forms
     };
 
-    Ok(())
+    for form in forms {
+        match raw_text(&form) {
+            Some(text) if opts.raw => try!(writer.write_all(text.as_bytes())),
+            _ => try!(write_form(&mut writer, form, opts)),
+        }
+
+        try!(writer.write_all(opts.separator.as_bytes()));
+    }
+
+    writer.flush()
+}
+
+crate fn format_output(forms: Vec<EdnValue>, opts: &OutputOptions) -> io::Result<()> {
+    let stdout = io::stdout();
+
+    match &opts.destination {
+        OutputDestination::Stdout => write_output(stdout.lock(), forms, opts),
+        OutputDestination::File(_path) => write_output(stdout.lock(), forms, opts),
+    }
+}
+
+/// Writes a value compactly in the theme's colors, for use within a line of
+/// other text.
+fn write_inline<W: Write>(writer: W, value: EdnValue, opts: &OutputOptions) -> io::Result<()> {
+    CompactEdnFormatter::new(writer, opts.theme.clone(), opts.rainbow).write_form(value)
+}
+
+/// Writes a structural diff as text, one change per line: `+ path value` for
+/// an addition, `- path value` for a removal and `~ path old -> new` for a
+/// change.
+fn write_diff<W: Write>(
+    mut writer: W,
+    changes: Vec<Change>,
+    opts: &OutputOptions,
+) -> io::Result<()> {
+    let theme = &opts.theme;
+
+    for change in changes {
+        let (marker, path, values) = match change {
+            Change::Added(path, value) => (theme.added.paint("+"), path, vec![value]),
+            Change::Removed(path, value) => (theme.removed.paint("-"), path, vec![value]),
+            Change::Changed(path, old, new) => (theme.changed.paint("~"), path, vec![old, new]),
+        };
+
+        try!(write!(writer, "{} ", marker));
+        try!(write_inline(&mut writer, EdnValue::Vector(path), opts));
+
+        for (idx, value) in values.into_iter().enumerate() {
+            try!(writer.write_all(if idx == 0 { b" " } else { b" -> " }));
+            try!(write_inline(&mut writer, value, opts));
+        }
+
+        try!(writer.write_all(b"\n"));
+    }
+
+    writer.flush()
+}
+
+/// Writes the changes found by `--diff`, either as text or as EDN data
+/// forms written like any other output.
+crate fn format_diff(
+    changes: Vec<Change>,
+    format: &DiffFormat,
+    opts: &OutputOptions,
+) -> io::Result<()> {
+    match format {
+        DiffFormat::Text => write_diff(io::stdout().lock(), changes, opts),
+        DiffFormat::EDN => {
+            format_output(changes.into_iter().map(Change::into_edn).collect(), opts)
+        }
+    }
 }
 
-/* #[cfg(test)]
+#[cfg(test)]
 mod format_tests {
     use super::*;
     use std::collections::BTreeSet;
     use std::iter::FromIterator;
 
+    use super::super::input;
+
+    fn format_form(form: EdnValue) -> String {
+        colored::control::set_override(false);
+
+        let mut buf: Vec<u8> = vec![];
+        CompactEdnFormatter::new(&mut buf, ColorTheme::default(), false)
+            .write_form(form)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn format_pretty(form: EdnValue) -> String {
+        colored::control::set_override(false);
+
+        let mut buf: Vec<u8> = vec![];
+        PrettyEdnFormatter::new(&mut buf, 80, ColorTheme::default(), false)
+            .write_form(form)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn format_width(form: EdnValue, width: usize) -> String {
+        colored::control::set_override(false);
+
+        let mut buf: Vec<u8> = vec![];
+        PrettyEdnFormatter::new(&mut buf, width, ColorTheme::default(), false)
+            .write_form(form)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn options() -> OutputOptions {
+        OutputOptions {
+            format: OutputFormat::EDN,
+            style: OutputStyle::Compact,
+            width: 80,
+            theme: ColorTheme::plain(),
+            rainbow: false,
+            canonical: false,
+            sort_keys: false,
+            raw: false,
+            separator: "\n".to_owned(),
+            slurp_output: false,
+            destination: OutputDestination::Stdout,
+        }
+    }
+
+    fn write_all(forms: Vec<EdnValue>, opts: &OutputOptions) -> String {
+        let mut buf: Vec<u8> = vec![];
+        write_output(&mut buf, forms, opts).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn assert_round_trip(form: EdnValue) {
+        for formatted in vec![format_form(form.clone()), format_pretty(form.clone())] {
+            let parsed = input::parse_edn(&formatted).unwrap();
+            assert_eq!(parsed, vec![form.clone()], "round trip of {}", formatted);
+        }
+    }
+
     #[test]
     fn test_nil() {
         assert_eq!(format_form(EdnValue::Nil), "nil");
@@ -693,12 +1252,18 @@ mod format_tests {
             format_form(EdnValue::String("hello world".to_owned())),
             "\"hello world\""
         );
+        assert_eq!(
+            format_form(EdnValue::String("say \"hi\"\\\n".to_owned())),
+            "\"say \\\"hi\\\"\\\\\\n\""
+        );
     }
 
     #[test]
     fn test_char() {
         assert_eq!(format_form(EdnValue::Char('c')), "\\c");
         assert_eq!(format_form(EdnValue::Char('\\')), "\\\\");
+        assert_eq!(format_form(EdnValue::Char('\n')), "\\newline");
+        assert_eq!(format_form(EdnValue::Char(' ')), "\\space");
     }
 
     #[test]
@@ -768,4 +1333,196 @@ mod format_tests {
             "#{true}"
         );
     }
-} */
+
+    #[test]
+    fn test_float() {
+        assert_eq!(format_form(EdnValue::from(1.0)), "1.0");
+        assert_eq!(format_form(EdnValue::from(-0.5)), "-0.5");
+        assert_eq!(format_form(EdnValue::from(std::f64::NAN)), "##NaN");
+        assert_eq!(format_pretty(EdnValue::from(std::f64::NEG_INFINITY)), "##-Inf");
+    }
+
+    #[test]
+    fn test_pretty_layout() {
+        let numbers = EdnValue::Vector(vec![
+            EdnValue::Integer(1),
+            EdnValue::Integer(2),
+            EdnValue::Integer(3),
+        ]);
+        let config = EdnValue::Map(
+            vec![
+                (EdnValue::Keyword("a".to_owned()), numbers.clone()),
+                (
+                    EdnValue::Keyword("b".to_owned()),
+                    EdnValue::String("long string here".to_owned()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        assert_eq!(format_width(numbers.clone(), 80), "[1 2 3]");
+        assert_eq!(format_width(numbers.clone(), 4), "[1\n 2\n 3]");
+        assert_eq!(
+            format_width(config.clone(), 80),
+            "{:a [1 2 3] :b \"long string here\"}"
+        );
+        assert_eq!(
+            format_width(config, 20),
+            "{:a [1 2 3]\n :b \"long string here\"}"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_scalars() {
+        for s in &["", "plain", "quote \" inside", "back\\slash", "tab\tnew\nline\r"] {
+            assert_round_trip(EdnValue::String(s.to_string()));
+        }
+
+        for c in &['a', '\\', '"', '\n', ' ', '\t', '\r', '('] {
+            assert_round_trip(EdnValue::Char(*c));
+        }
+
+        assert_round_trip(EdnValue::Keyword("ns/name".to_owned()));
+        assert_round_trip(EdnValue::Integer(-42));
+        let floats = vec![2.5, 1.0, -3.0, 1e21, 1e-7];
+        let symbolic = vec![std::f64::NAN, std::f64::INFINITY, std::f64::NEG_INFINITY];
+        for f in floats.into_iter().chain(symbolic) {
+            assert_round_trip(EdnValue::from(f));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_collections() {
+        let nested = EdnValue::Map(
+            vec![
+                (
+                    EdnValue::Keyword("a".to_owned()),
+                    EdnValue::Vector(vec![
+                        EdnValue::String("x\"y".to_owned()),
+                        EdnValue::Char('\n'),
+                        EdnValue::Nil,
+                    ]),
+                ),
+                (
+                    EdnValue::String("key\\".to_owned()),
+                    EdnValue::Set(BTreeSet::from_iter(vec![
+                        EdnValue::Integer(1),
+                        EdnValue::List(vec![EdnValue::Symbol("f".to_owned())]),
+                    ])),
+                ),
+                (
+                    EdnValue::Keyword("at".to_owned()),
+                    EdnValue::Tagged(
+                        "inst".to_owned(),
+                        Box::new(EdnValue::String("2018-01-01T00:00:00Z".to_owned())),
+                    ),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        assert_round_trip(nested);
+    }
+
+    #[test]
+    fn test_canonical() {
+        let forms = input::parse_edn("{:b #{2 1.5 :x} \"a\" (1) 10 nil} [2 1]").unwrap();
+        let opts = OutputOptions {
+            canonical: true,
+            ..options()
+        };
+
+        assert_eq!(
+            write_all(forms, &opts),
+            "{10 nil \"a\" (1) :b #{1.5 2 :x}}\n[2 1]\n"
+        );
+
+        let first = input::parse_edn("{5 :a ##NaN :b 1M :c}").unwrap();
+        let second = input::parse_edn("{1M :c 5 :a ##NaN :b}").unwrap();
+        assert_eq!(write_all(first, &opts), "{1M :c 5 :a ##NaN :b}\n");
+        assert_eq!(write_all(second, &opts), "{1M :c 5 :a ##NaN :b}\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let forms = input::parse_edn("{:b [1 2N #{}] \"a\" {:c nil} 1/4 true}").unwrap();
+
+        let write = |pretty: bool| {
+            let mut buf: Vec<u8> = vec![];
+            JsonFormatter::new(&mut buf, pretty, true, ColorTheme::plain())
+                .write_form(forms[0].clone())
+                .unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        assert_eq!(
+            write(false),
+            "{\"1/4\":true,\"a\":{\"c\":null},\"b\":[1,2,[]]}"
+        );
+        assert_eq!(
+            write(true),
+            "{\n  \"1/4\": true,\n  \"a\": {\n    \"c\": null\n  },\n  \"b\": [\n    1,\n    2,\n    []\n  ]\n}"
+        );
+
+        let colliding = input::parse_edn("{\"a\" 1 :a 2}").unwrap();
+        let error = JsonFormatter::new(Vec::<u8>::new(), false, true, ColorTheme::plain())
+            .write_form(colliding[0].clone())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "more than one map key becomes the JSON key \"a\"");
+    }
+
+    #[test]
+    fn test_raw_output() {
+        let forms = input::parse_edn("\"db.local\" :port \"a\\\"b\" [\"x\"]").unwrap();
+        let raw = OutputOptions {
+            raw: true,
+            ..options()
+        };
+
+        assert_eq!(write_all(forms.clone(), &raw), "db.local\nport\na\"b\n[\"x\"]\n");
+        assert_eq!(
+            write_all(forms, &OutputOptions {
+                separator: "".to_owned(),
+                ..raw
+            }),
+            "db.localporta\"b[\"x\"]"
+        );
+    }
+
+    #[test]
+    fn test_separators() {
+        let forms = input::parse_edn("1 :a").unwrap();
+
+        let nul = OutputOptions {
+            separator: "\0".to_owned(),
+            ..options()
+        };
+        assert_eq!(write_all(forms.clone(), &nul), "1\0:a\0");
+
+        let slurped = OutputOptions {
+            slurp_output: true,
+            ..options()
+        };
+        assert_eq!(write_all(forms, &slurped), "[1 :a]\n");
+    }
+
+    #[test]
+    fn test_diff_text() {
+        let kw = |name: &str| EdnValue::Keyword(name.to_owned());
+        let changes = vec![
+            Change::Changed(vec![kw("db"), kw("port")], EdnValue::Integer(1), EdnValue::Integer(2)),
+            Change::Added(vec![kw("debug")], EdnValue::Boolean(true)),
+            Change::Removed(vec![kw("hosts"), EdnValue::Integer(0)], EdnValue::from("a")),
+        ];
+
+        let mut buf: Vec<u8> = vec![];
+        write_diff(&mut buf, changes, &options()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "~ [:db :port] 1 -> 2\n+ [:debug] true\n- [:hosts 0] \"a\"\n"
+        );
+    }
+}
@@ -1,75 +1,547 @@
-use std::str;
+use std::collections::BTreeMap;
+use std::fmt;
 
-use super::transform::*;
-use edn::Value;
+use colored::*;
+use nom::types::CompleteStr;
 use nom::*;
 
-fn keyword_to_get_op(keyword: &[u8]) -> Box<dyn Operation> {
-    Box::new(
-        GetOperation {
-        key: Value::Keyword(String::from(str::from_utf8(keyword).unwrap())),
+use super::builtins;
+use super::number;
+use super::transform::*;
+use super::value::Value;
+
+type Op = Box<dyn Operation>;
+
+/// Named values bound on the command line with `--arg` and `--argedn`.
+crate type Variables = BTreeMap<String, Value>;
+
+#[derive(Debug)]
+crate struct ExpressionError {
+    expression: String,
+    column: usize,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} invalid expression at column {}\n  {}\n  {}{}",
+            "error:".red().bold(),
+            self.column,
+            self.expression,
+            " ".repeat(self.column - 1),
+            "^".red().bold()
+        )
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    c.is_whitespace() || c == ','
+}
+
+fn is_keyword_char(c: char) -> bool {
+    c.is_alphanumeric() || "*+!-_?<>=/.#$%&'".contains(c)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_?!*".contains(c)
+}
+
+named!(space<CompleteStr<'_>, CompleteStr<'_> >, take_while!(is_whitespace));
+
+/// A string literal in double quotes, with the same escapes as EDN strings.
+fn string_literal(input: CompleteStr<'_>) -> IResult<CompleteStr<'_>, String> {
+    let fail = || Err(Err::Error(Context::Code(input, ErrorKind::Custom(0))));
+
+    if !input.0.starts_with('"') {
+        return fail();
+    }
+
+    let mut value = String::new();
+    let mut chars = input.0.char_indices().skip(1);
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((CompleteStr(&input.0[idx + 1..]), value)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, escaped)) => value.push(escaped),
+                None => return fail(),
+            },
+            _ => value.push(c),
+        }
+    }
+
+    fail()
+}
+
+/// A regex literal, `#"..."`, read as the string of its pattern. As in
+/// Clojure, backslashes are kept as they are, so `#"\d+"` needs no double
+/// escaping; only `\"` is unescaped.
+fn regex_literal(input: CompleteStr<'_>) -> IResult<CompleteStr<'_>, String> {
+    let fail = || Err(Err::Error(Context::Code(input, ErrorKind::Custom(0))));
+
+    if !input.0.starts_with("#\"") {
+        return fail();
+    }
+
+    let mut pattern = String::new();
+    let mut chars = input.0.char_indices().skip(2);
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((CompleteStr(&input.0[idx + 1..]), pattern)),
+            '\\' => match chars.next() {
+                Some((_, '"')) => pattern.push('"'),
+                Some((_, escaped)) => {
+                    pattern.push('\\');
+                    pattern.push(escaped);
+                }
+                None => return fail(),
+            },
+            _ => pattern.push(c),
+        }
+    }
+
+    fail()
+}
+
+/// A number in any of the forms EDN allows, such as `-1`, `2.5`, `10N` or
+/// `1/3`.
+fn number_literal(input: CompleteStr<'_>) -> IResult<CompleteStr<'_>, Value> {
+    let end = input
+        .0
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "+-./".contains(c)))
+        .unwrap_or_else(|| input.0.len());
+
+    match number::parse_literal(&input.0[..end]) {
+        Some(value) => Ok((CompleteStr(&input.0[end..]), value)),
+        None => Err(Err::Error(Context::Code(input, ErrorKind::Custom(0)))),
+    }
+}
+
+/// The name of a builtin, such as `keys` or `starts-with?`.
+named!(identifier<CompleteStr<'_>, CompleteStr<'_> >,
+    verify!(take_while1!(is_identifier_char), |name: CompleteStr<'_>| {
+        name.0.starts_with(char::is_alphabetic)
     })
+);
+
+named!(literal_keyword<CompleteStr<'_>, Value>,
+    map!(
+        preceded!(char!(':'), take_while1!(is_keyword_char)),
+        |name: CompleteStr<'_>| Value::Keyword(name.0.to_owned())
+    )
+);
+
+named!(named_constant<CompleteStr<'_>, Value>,
+    map_opt!(identifier, |name: CompleteStr<'_>| match name.0 {
+        "nil" => Some(Value::Nil),
+        "true" => Some(Value::Boolean(true)),
+        "false" => Some(Value::Boolean(false)),
+        _ => None,
+    })
+);
+
+/// A tagged literal, such as `#inst "2026-01-01"`.
+named!(tagged_literal<CompleteStr<'_>, Value>,
+    do_parse!(
+        char!('#') >>
+        tag: take_while1!(is_keyword_char) >>
+        value: preceded!(space, constant) >>
+        (Value::Tagged(tag.0.to_owned(), Box::new(value)))
+    )
+);
+
+named!(constant<CompleteStr<'_>, Value>,
+    alt!(
+        map!(string_literal, Value::String) |
+        map!(regex_literal, Value::String) |
+        named_constant |
+        tagged_literal |
+        number_literal
+    )
+);
+
+named_args!(variable<'a>(vars: &'a Variables)<CompleteStr<'a>, Value>,
+    map_opt!(
+        preceded!(char!('$'), take_while1!(is_keyword_char)),
+        |name: CompleteStr<'_>| vars.get(name.0).cloned()
+    )
+);
+
+/// Recursive descent, `..`, the same as calling `recurse`.
+named!(recurse<CompleteStr<'_>, Op>,
+    map_opt!(tag!(".."), |_| builtins::lookup("recurse", vec![]))
+);
+
+named!(identity<CompleteStr<'_>, Op>,
+    map!(char!('.'), |_| Box::new(IdentityOperation {}) as Op)
+);
+
+named!(keyword<CompleteStr<'_>, Op>,
+    map!(literal_keyword, |key| Box::new(GetOperation { key }) as Op)
+);
+
+fn to_op(operations: Vec<Op>) -> Op {
+    Box::new(PipelineOperation { operations })
 }
 
-fn is_whitespace(c: u8) -> bool {
-    c.is_ascii_whitespace() || c == b','
+named_args!(arguments<'a>(vars: &'a Variables)<CompleteStr<'a>, Vec<Op> >,
+    delimited!(
+        char!('('),
+        separated_nonempty_list!(
+            preceded!(space, char!(';')),
+            map!(call!(pipeline, vars), to_op)
+        ),
+        preceded!(space, char!(')'))
+    )
+);
+
+fn resolve_call((name, args): (CompleteStr<'_>, Option<Vec<Op>>)) -> Option<Op> {
+    builtins::lookup(name.0, args.unwrap_or_default())
 }
 
-fn isnt_whitespace(c: u8) -> bool {
-    println!("is whitespace? {:?} -> {}", c, is_whitespace(c));
-    !is_whitespace(c)
+/// A call of a builtin, either bare (`keys`) or with arguments (`map(:a)`).
+named_args!(function_call<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    map_opt!(pair!(identifier, opt!(call!(arguments, vars))), resolve_call)
+);
+
+named_args!(vector<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    map!(
+        delimited!(char!('['), many0!(call!(pipeline, vars)), preceded!(space, char!(']'))),
+        |items: Vec<Vec<Op>>| Box::new(ConstructVectorOperation {
+            items: items.into_iter().map(to_op).collect(),
+        }) as Op
+    )
+);
+
+named_args!(map_entry<'a>(vars: &'a Variables)<CompleteStr<'a>, (Value, Op)>,
+    do_parse!(
+        key: preceded!(space, alt!(literal_keyword | constant | call!(variable, vars))) >>
+        value: call!(pipeline, vars) >>
+        ((key, to_op(value)))
+    )
+);
+
+named_args!(map_literal<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    map!(
+        delimited!(char!('{'), many0!(call!(map_entry, vars)), preceded!(space, char!('}'))),
+        |entries| Box::new(ConstructMapOperation { entries }) as Op
+    )
+);
+
+named_args!(term<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    preceded!(space, alt!(
+        call!(vector, vars) |
+        call!(map_literal, vars) |
+        map!(call!(variable, vars), |value| Box::new(ConstantOperation { value }) as Op) |
+        keyword |
+        map!(constant, |value| Box::new(ConstantOperation { value }) as Op) |
+        call!(function_call, vars) |
+        recurse |
+        identity
+    ))
+);
+
+/// An arithmetic operator. It must be followed by whitespace, so that it is
+/// never taken for the sign of a number.
+macro_rules! arithmetic_operator (
+    ($i:expr, $operator:expr, $c:expr) => (
+        value!($i, $operator, terminated!(char!($c), peek!(take_while1!(is_whitespace))))
+    );
+);
+
+named!(additive_operator<CompleteStr<'_>, number::Operator>,
+    alt!(
+        arithmetic_operator!(number::Operator::Add, '+') |
+        arithmetic_operator!(number::Operator::Subtract, '-')
+    )
+);
+
+named!(multiplicative_operator<CompleteStr<'_>, number::Operator>,
+    alt!(
+        arithmetic_operator!(number::Operator::Multiply, '*') |
+        arithmetic_operator!(number::Operator::Divide, '/')
+    )
+);
+
+fn to_arithmetic((first, rest): (Op, Vec<(number::Operator, Op)>)) -> Op {
+    rest.into_iter().fold(first, |lhs, (operator, rhs)| {
+        Box::new(ArithmeticOperation { operator, lhs, rhs }) as Op
+    })
 }
 
-named!(identity<&[u8], Box<dyn Operation> >,
-    value!(Box::new(IdentityOperation {}), char!('.'))
+named_args!(product<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    map!(
+        pair!(
+            call!(term, vars),
+            many0!(pair!(preceded!(space, multiplicative_operator), call!(term, vars)))
+        ),
+        to_arithmetic
+    )
 );
 
-named!(keyword<&[u8], Box<dyn Operation> >,
-    map!(preceded!(char!(':'), take_while1!(isnt_whitespace)), keyword_to_get_op)
+/// Terms combined with `+ - * /`, with the usual precedence and left to
+/// right: `:price * :qty - :discount`.
+named_args!(sum<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    map!(
+        pair!(
+            call!(product, vars),
+            many0!(pair!(preceded!(space, additive_operator), call!(product, vars)))
+        ),
+        to_arithmetic
+    )
 );
 
-named!(
-    map,
-    delimited!(tag!("map("), take_while1!(isnt_whitespace), char!(')'))
+named!(comparison_operator<CompleteStr<'_>, Comparison>,
+    alt!(
+        value!(Comparison::Equal, tag!("==")) |
+        value!(Comparison::NotEqual, tag!("!=")) |
+        value!(Comparison::LessOrEqual, tag!("<=")) |
+        value!(Comparison::GreaterOrEqual, tag!(">=")) |
+        value!(Comparison::Less, tag!("<")) |
+        value!(Comparison::Greater, tag!(">"))
+    )
 );
 
-named!(expr<&[u8], Box<dyn Operation> >, alt!(
-    identity | keyword
-));
+/// A sum, optionally compared with another: `:ts > #inst "2026-01-01"`.
+named_args!(comparison<'a>(vars: &'a Variables)<CompleteStr<'a>, Op>,
+    do_parse!(
+        lhs: call!(sum, vars) >>
+        rhs: opt!(pair!(preceded!(space, comparison_operator), call!(sum, vars))) >>
+        (match rhs {
+            Some((comparison, rhs)) => Box::new(CompareOperation { comparison, lhs, rhs }) as Op,
+            None => lhs,
+        })
+    )
+);
+
+named_args!(pipeline<'a>(vars: &'a Variables)<CompleteStr<'a>, Vec<Op> >,
+    do_parse!(
+        first: call!(comparison, vars) >>
+        rest: many0!(preceded!(preceded!(space, char!('|')), call!(comparison, vars))) >>
+        ({
+            let mut operations = vec![first];
+            operations.extend(rest);
+            operations
+        })
+    )
+);
+
+named_args!(expression<'a>(vars: &'a Variables)<CompleteStr<'a>, Vec<Op> >,
+    terminated!(call!(pipeline, vars), preceded!(space, eof!()))
+);
+
+/// Parses an eq expression into the pipeline of operations it describes.
+/// Variables (`$name`) are replaced by their values from `vars` as they are
+/// parsed; an unknown variable is an error.
+crate fn parse_expression(input: &str, vars: &Variables) -> Result<Vec<Op>, ExpressionError> {
+    let remaining = match expression(CompleteStr(input), vars) {
+        Ok((_, operations)) => return Ok(operations),
+        Err(Err::Error(Context::Code(rest, _))) | Err(Err::Failure(Context::Code(rest, _))) => {
+            rest.0.len()
+        }
+        Err(_) => 0,
+    };
+
+    Err(ExpressionError {
+        expression: input.to_owned(),
+        column: input[..input.len() - remaining].chars().count() + 1,
+    })
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn run(expression: &str, input: Value) -> Value {
+        let mut vars = Variables::new();
+        vars.insert("env".to_owned(), Value::from("prod"));
+
+        let operations = parse_expression(expression, &vars).unwrap();
+        PipelineOperation { operations }.execute(input).unwrap()
+    }
+
+    fn map_of(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Value::Keyword(k.to_owned()), v))
+                .collect(),
+        )
+    }
+
     #[test]
     pub fn test_identity() {
-        assert_eq!(identity(b"."), Ok((&b""[..], IdentityOperation {})));
-        assert_eq!(identity(b".."), Ok((&b"."[..], IdentityOperation {})));
+        assert_eq!(run(".", Value::Integer(1)), Value::Integer(1));
+        assert_eq!(run(" . | . ", Value::Integer(1)), Value::Integer(1));
     }
 
     #[test]
     pub fn test_keyword() {
+        let input = map_of(vec![("abc", map_of(vec![("def", Value::Integer(2))]))]);
+
+        assert_eq!(run(":abc | :def", input.clone()), Value::Integer(2));
+        assert_eq!(run(":missing", input), Value::Nil);
+    }
+
+    #[test]
+    pub fn test_map() {
+        let input = Value::Vector(vec![
+            map_of(vec![("a", Value::Integer(1))]),
+            map_of(vec![("a", Value::Integer(2))]),
+        ]);
+
         assert_eq!(
-            keyword(b":abc\n"),
-            Ok((
-                &b"\n"[..],
-                GetOperation {
-                    key: Value::Keyword("abc".to_owned())
-                }
-            ))
+            run("map(:a)", input),
+            Value::Vector(vec![Value::Integer(1), Value::Integer(2)])
         );
+    }
+
+    #[test]
+    pub fn test_arithmetic() {
+        let literal = |s| number::parse_literal(s).unwrap();
+        let input = map_of(vec![
+            ("price", literal("1.10M")),
+            ("qty", Value::Integer(3)),
+            ("max", Value::Integer(std::i64::MAX)),
+            ("third", literal("1/3")),
+        ]);
+
+        assert_eq!(run(":price * :qty", input.clone()), literal("3.30M"));
+        assert_eq!(run(":max + :qty", input.clone()), literal("9223372036854775810N"));
+        assert_eq!(run(":third + :third * :qty", input.clone()), literal("4/3"));
+        assert_eq!(run(":qty / :max", input.clone()), literal("3/9223372036854775807"));
         assert_eq!(
-            keyword(b":abc def"),
-            Ok((
-                &b" def"[..],
-                GetOperation {
-                    key: Value::Keyword("abc".to_owned())
-                }
-            ))
+            run("[5 - 2 * 3 7 / 2 -1 1/3 + 1/6 0.1M + 0.2M]", Value::Nil),
+            Value::Vector(vec![
+                Value::Integer(-1),
+                literal("7/2"),
+                Value::Integer(-1),
+                literal("1/2"),
+                literal("0.3M"),
+            ])
+        );
+
+        let operations = parse_expression(":qty - :missing", &Variables::new()).unwrap();
+        assert!(PipelineOperation { operations }.execute(input).is_err());
+    }
+
+    #[test]
+    pub fn test_invalid() {
+        let vars = Variables::new();
+
+        assert_eq!(parse_expression(":a | ?", &vars).unwrap_err().column, 4);
+        assert!(parse_expression("[1 $missing]", &vars).is_err());
+    }
+
+    #[test]
+    pub fn test_construction() {
+        assert_eq!(
+            run("{:env $env :ports [80 443] \"name\" :a}", map_of(vec![("a", Value::Nil)])),
+            Value::Map(
+                vec![
+                    (Value::Keyword("env".to_owned()), Value::from("prod")),
+                    (
+                        Value::Keyword("ports".to_owned()),
+                        Value::Vector(vec![Value::Integer(80), Value::Integer(443)]),
+                    ),
+                    (Value::from("name"), Value::Nil),
+                ]
+                .into_iter()
+                .collect(),
+            )
+        );
+        assert_eq!(
+            run("[nil true \"a\\\"b\" -1.5 1/2]", Value::Nil),
+            Value::Vector(vec![
+                Value::Nil,
+                Value::Boolean(true),
+                Value::from("a\"b"),
+                Value::from(-1.5),
+                number::parse_literal("1/2").unwrap(),
+            ])
         );
     }
-}
 
-/*named!(pub expr, alt!(
-    identity | keyword
-));*/
+    fn inst(s: &str) -> Value {
+        Value::Tagged("inst".to_owned(), Box::new(Value::from(s)))
+    }
+
+    #[test]
+    pub fn test_comparison() {
+        let (t, f) = (Value::Boolean(true), Value::Boolean(false));
+
+        assert_eq!(
+            run("[1 == 1.0 . != 2 . < 3 . >= 3]", Value::Integer(2)),
+            Value::Vector(vec![t.clone(), f.clone(), t, f])
+        );
+    }
+
+    #[test]
+    pub fn test_recurse() {
+        let inner = map_of(vec![("id", Value::Integer(1))]);
+        let input = map_of(vec![("a", inner), ("id", Value::Integer(2))]);
+        let nils = Value::Vector(vec![Value::Nil, Value::Vector(vec![Value::Nil])]);
+
+        assert_eq!(run("..", input.clone()), run("recurse", input.clone()));
+        assert_eq!(
+            run(".. | map(select(. == 1))", input),
+            Value::Vector(vec![Value::Integer(1)])
+        );
+        assert_eq!(
+            run("walk(select(. != nil))", nils),
+            Value::Vector(vec![Value::Vector(vec![])])
+        );
+    }
+
+    #[test]
+    pub fn test_regex() {
+        assert_eq!(
+            run("[re-find(#\"(\\d+)\\.(\\d+)\") test(#\"^\\\"\")]", Value::from("v1.25")),
+            Value::Vector(vec![
+                Value::Vector(vec![Value::from("1.25"), Value::from("1"), Value::from("25")]),
+                Value::Boolean(false),
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_strings() {
+        let hosts = Value::Vector(vec![Value::from("prod-db"), Value::from("dev-db")]);
+
+        assert_eq!(
+            run("map(select(starts-with?(\"prod\")) | upper-case) | join(\",\")", hosts),
+            Value::from("PROD-DB")
+        );
+    }
+
+    #[test]
+    pub fn test_select() {
+        let entry = |ts: &str| map_of(vec![("ts", inst(ts))]);
+        let vars = Variables::new();
+        let operations = parse_expression("select(:ts > #inst \"2026-01-01\")", &vars).unwrap();
+
+        let output = transform_edn(
+            vec![entry("2025-12-31T23:00:00-02:00"), entry("2025-06-01T00:00:00Z")],
+            &operations,
+        )
+        .unwrap();
+
+        assert_eq!(output, vec![entry("2025-12-31T23:00:00-02:00")]);
+    }
+
+    #[test]
+    pub fn test_functions() {
+        let ts = inst("2026-03-04T05:06:07Z");
+
+        assert_eq!(
+            run("[truncate(:day) | format-inst(\"%F %T\") duration(#inst \"2026-03-04\")]", ts),
+            Value::Vector(vec![Value::from("2026-03-04 00:00:00"), Value::Integer(18_367_000)])
+        );
+        assert!(parse_expression("truncate", &Variables::new()).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use super::map::Map;
+use super::transform::*;
+use super::value::Value;
+
+/// A path through a form: the map keys and collection indices leading from
+/// the root to one of its values, as in `[:db :hosts 0]`.
+crate type Path = Vec<Value>;
+
+fn is_collection(value: &Value) -> bool {
+    match value {
+        Value::List(_) | Value::Vector(_) | Value::Map(_) => true,
+        _ => false,
+    }
+}
+
+/// Every path within a value paired with the value it leads to, parents
+/// before their children. The root's own empty path is not included. Sets and
+/// tagged literals are not descended into.
+fn paths_of(value: &Value) -> Vec<(Path, &Value)> {
+    let mut found = vec![];
+    collect_paths(value, &mut vec![], &mut found);
+    found
+}
+
+fn collect_paths<'v>(value: &'v Value, path: &mut Path, found: &mut Vec<(Path, &'v Value)>) {
+    let mut visit = |key: Value, child: &'v Value| {
+        path.push(key);
+        found.push((path.clone(), child));
+        collect_paths(child, path, found);
+        path.pop();
+    };
+
+    match value {
+        Value::List(items) | Value::Vector(items) => {
+            for (idx, item) in items.iter().enumerate() {
+                visit(Value::Integer(idx as i64), item);
+            }
+        }
+        Value::Map(m) => {
+            for (k, v) in m.iter() {
+                visit(k.clone(), v);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn path_arg(function: &str, value: &Value) -> Result<Path, OperationError> {
+    match value {
+        Value::List(path) | Value::Vector(path) => Ok(path.clone()),
+        _ => Err(fail(format!(
+            "'{}' needs a path vector, got {}",
+            function,
+            value_type_name(value)
+        ))),
+    }
+}
+
+/// The position an index stands for in a collection of `len` items, if it
+/// is an integer within `0..len`.
+fn position(key: &Value, len: usize) -> Option<usize> {
+    match key {
+        Value::Integer(idx) if *idx >= 0 && (*idx as usize) < len => Some(*idx as usize),
+        _ => None,
+    }
+}
+
+/// The value at a path. A step into a set is a member of it, so that the
+/// paths `diff` gives to set members can be followed.
+fn get_in<'v>(value: &'v Value, path: &[Value]) -> Result<Option<&'v Value>, OperationError> {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(Some(value)),
+    };
+
+    let child = match value {
+        Value::Nil => None,
+        Value::Map(m) => m.get(key),
+        Value::Set(items) => items.get(key),
+        Value::List(items) | Value::Vector(items) => match key {
+            Value::Integer(_) => position(key, items.len()).map(|idx| &items[idx]),
+            _ => return Err(fail(format!("Can not index a vector with {}", value_type_name(key)))),
+        },
+        _ => {
+            return Err(fail(format!(
+                "Can not follow a path into {}",
+                value_type_name(value)
+            )))
+        }
+    };
+
+    match child {
+        Some(child) => get_in(child, rest),
+        None => Ok(None),
+    }
+}
+
+fn set_in(value: Value, path: &[Value], new: Value) -> OperationResult {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(new),
+    };
+
+    match (value, key) {
+        (Value::Nil, Value::Integer(_)) => set_in(Value::Vector(vec![]), path, new),
+        (Value::Nil, _) => set_in(Value::Map(Map::new()), path, new),
+        (Value::Map(mut m), _) => {
+            let child = m.get(key).cloned().unwrap_or(Value::Nil);
+            m.insert(key.clone(), set_in(child, rest, new)?);
+            Ok(Value::Map(m))
+        }
+        (Value::Vector(mut items), Value::Integer(idx)) => {
+            // An index may be one past the end, to append, but no further.
+            if *idx < 0 || *idx as u64 > items.len() as u64 {
+                return Err(fail(format!(
+                    "Can not set index {} within a vector of {} items",
+                    idx,
+                    items.len()
+                )));
+            }
+
+            let idx = *idx as usize;
+            if idx == items.len() {
+                items.push(Value::Nil);
+            }
+            let child = items[idx].clone();
+            items[idx] = set_in(child, rest, new)?;
+            Ok(Value::Vector(items))
+        }
+        (Value::List(items), _) => set_in(Value::Vector(items), path, new).map(|set| match set {
+            Value::Vector(items) => Value::List(items),
+            other => other,
+        }),
+        (value, _) => Err(fail(format!(
+            "Can not set {} within {}",
+            value_type_name(key),
+            value_type_name(&value)
+        ))),
+    }
+}
+
+/// Removes the value at a path. As in `get_in`, a step into a set is a
+/// member of it.
+fn delete_in(value: Value, path: &[Value]) -> OperationResult {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(Value::Nil),
+    };
+
+    match value {
+        Value::Map(mut m) => {
+            if rest.is_empty() {
+                m.remove(key);
+            } else if let Some(child) = m.get(key).cloned() {
+                m.insert(key.clone(), delete_in(child, rest)?);
+            }
+            Ok(Value::Map(m))
+        }
+        Value::Vector(mut items) => {
+            if let Some(idx) = position(key, items.len()) {
+                if rest.is_empty() {
+                    items.remove(idx);
+                } else {
+                    let child = items[idx].clone();
+                    items[idx] = delete_in(child, rest)?;
+                }
+            }
+            Ok(Value::Vector(items))
+        }
+        Value::Set(mut items) => {
+            if let Some(member) = items.take(key) {
+                if !rest.is_empty() {
+                    items.insert(delete_in(member, rest)?);
+                }
+            }
+            Ok(Value::Set(items))
+        }
+        Value::List(items) => delete_in(Value::Vector(items), path).map(|deleted| match deleted {
+            Value::Vector(items) => Value::List(items),
+            other => other,
+        }),
+        other => Ok(other),
+    }
+}
+
+/// `paths`: every path within the input.
+crate fn paths(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::Vector(
+        paths_of(&input)
+            .into_iter()
+            .map(|(path, _)| Value::Vector(path))
+            .collect(),
+    ))
+}
+
+/// `leaf-paths`: the paths within the input which lead to values other than
+/// lists, vectors and maps.
+crate fn leaf_paths(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::Vector(
+        paths_of(&input)
+            .into_iter()
+            .filter(|(_, value)| !is_collection(value))
+            .map(|(path, _)| Value::Vector(path))
+            .collect(),
+    ))
+}
+
+/// `get-path(path)`: the value at a path, or nil if there is none.
+crate fn get_path(input: Value, args: Vec<Value>) -> OperationResult {
+    let path = path_arg("get-path", &args[0])?;
+    Ok(get_in(&input, &path)?.cloned().unwrap_or(Value::Nil))
+}
+
+/// `set-path(path; value)`: replaces the value at a path, creating maps and
+/// vectors along the way where there are none. An index may add an item to
+/// the end of a vector or list, but not leave a gap.
+crate fn set_path(input: Value, mut args: Vec<Value>) -> OperationResult {
+    let path = path_arg("set-path", &args[0])?;
+    set_in(input, &path, args.remove(1))
+}
+
+/// `del-paths(paths)`: removes the values at each of a vector of paths.
+crate fn del_paths(input: Value, args: Vec<Value>) -> OperationResult {
+    let mut paths = match &args[0] {
+        Value::List(paths) | Value::Vector(paths) => paths
+            .iter()
+            .map(|path| path_arg("del-paths", path))
+            .collect::<Result<Vec<_>, _>>()?,
+        other => {
+            return Err(fail(format!(
+                "'del-paths' needs a vector of paths, got {}",
+                value_type_name(other)
+            )))
+        }
+    };
+
+    // Delete the last paths first, so that removing an item from a vector
+    // does not shift the indices of those still to go, and each path only
+    // once.
+    paths.sort_by(|a, b| b.cmp(a));
+    paths.dedup();
+
+    paths
+        .iter()
+        .try_fold(input, |value, path| delete_in(value, path))
+}
+
+/// `paths(predicate)`: the paths within the input which lead to values the
+/// predicate holds for.
+crate struct PathsOperation {
+    crate predicate: Box<dyn Operation>,
+}
+
+impl Operation for PathsOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        let mut matching = vec![];
+
+        for (path, value) in paths_of(&input) {
+            match self.predicate.execute(value.clone()) {
+                Ok(ref result) if is_truthy(result) => matching.push(Value::Vector(path)),
+                Ok(_) | Err(OperationError::Empty) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Value::Vector(matching))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kw(name: &str) -> Value {
+        Value::Keyword(name.to_owned())
+    }
+
+    fn path(keys: Vec<Value>) -> Value {
+        Value::Vector(keys)
+    }
+
+    fn config() -> Value {
+        let db: Map = vec![(kw("hosts"), Value::Vector(vec![Value::from("a"), Value::from("b")]))]
+            .into_iter()
+            .collect();
+
+        Value::Map(vec![(kw("db"), Value::Map(db))].into_iter().collect())
+    }
+
+    #[test]
+    fn test_paths() {
+        let hosts = vec![kw("db"), kw("hosts")];
+        let host = |idx| path(vec![kw("db"), kw("hosts"), Value::Integer(idx)]);
+
+        assert_eq!(
+            paths(config(), vec![]).unwrap(),
+            Value::Vector(vec![path(vec![kw("db")]), path(hosts), host(0), host(1)])
+        );
+        assert_eq!(leaf_paths(config(), vec![]).unwrap(), Value::Vector(vec![host(0), host(1)]));
+        assert_eq!(get_path(config(), vec![host(1)]).unwrap(), Value::from("b"));
+        assert_eq!(get_path(config(), vec![host(2)]).unwrap(), Value::Nil);
+    }
+
+    #[test]
+    fn test_set_members() {
+        let tags = |items: Vec<&str>| {
+            let set = Value::Set(items.into_iter().map(Value::from).collect());
+            Value::Map(vec![(kw("tags"), set)].into_iter().collect())
+        };
+        let member = |name: &str| path(vec![kw("tags"), Value::from(name)]);
+
+        assert_eq!(get_path(tags(vec!["x", "y"]), vec![member("x")]).unwrap(), Value::from("x"));
+        assert_eq!(get_path(tags(vec!["x", "y"]), vec![member("z")]).unwrap(), Value::Nil);
+        assert_eq!(
+            del_paths(tags(vec!["x", "y"]), vec![Value::Vector(vec![member("x")])]).unwrap(),
+            tags(vec!["y"])
+        );
+    }
+
+    #[test]
+    fn test_set_and_delete() {
+        let port = path(vec![kw("db"), kw("port")]);
+        let set = set_path(config(), vec![port.clone(), Value::Integer(5432)]).unwrap();
+
+        assert_eq!(get_path(set.clone(), vec![port.clone()]).unwrap(), Value::Integer(5432));
+        assert_eq!(
+            set_path(Value::Nil, vec![path(vec![Value::Integer(0)]), Value::Integer(1)]).unwrap(),
+            Value::Vector(vec![Value::Integer(1)])
+        );
+        assert_eq!(
+            set_path(
+                Value::List(vec![Value::Integer(1)]),
+                vec![path(vec![Value::Integer(1)]), Value::Integer(2)]
+            )
+            .unwrap(),
+            Value::List(vec![Value::Integer(1), Value::Integer(2)])
+        );
+        let far = path(vec![Value::Integer(std::i64::MAX)]);
+        assert!(set_path(Value::Vector(vec![]), vec![far, Value::Nil]).is_err());
+
+        let both = Value::Vector(vec![
+            path(vec![kw("db"), kw("hosts"), Value::Integer(0)]),
+            path(vec![kw("db"), kw("hosts"), Value::Integer(1)]),
+            port,
+        ]);
+        assert_eq!(
+            del_paths(set, vec![both]).unwrap(),
+            set_path(config(), vec![path(vec![kw("db"), kw("hosts")]), Value::Vector(vec![])])
+                .unwrap()
+        );
+
+        let letters = Value::Vector(vec![kw("a"), kw("b"), kw("c")]);
+        let first = path(vec![Value::Integer(0)]);
+        let twice = Value::Vector(vec![first.clone(), first]);
+        assert_eq!(
+            del_paths(letters, vec![twice]).unwrap(),
+            Value::Vector(vec![kw("b"), kw("c")])
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+use super::map::Map;
+use super::transform::{fail, text, value_type_name, OperationError, OperationResult};
+use super::value::Value;
+
+/// The most patterns kept compiled at once. Patterns may come from the
+/// input, so the cache is emptied rather than left to grow without bound.
+const CACHED_PATTERNS: usize = 64;
+
+thread_local! {
+    /// Compiled patterns by their text, since a builtin runs once for every
+    /// value it is given.
+    static COMPILED: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+fn compile_cached(pattern: &str) -> Result<Regex, regex::Error> {
+    COMPILED.with(|compiled| {
+        if let Some(re) = compiled.borrow().get(pattern) {
+            return Ok(re.clone());
+        }
+
+        let re = Regex::new(pattern)?;
+        let mut compiled = compiled.borrow_mut();
+        if compiled.len() >= CACHED_PATTERNS {
+            compiled.clear();
+        }
+        compiled.insert(pattern.to_owned(), re.clone());
+        Ok(re)
+    })
+}
+
+/// Compiles the pattern argument of a regex builtin. Patterns are plain
+/// strings, usually written with the `#"..."` literal so that backslashes
+/// need no escaping.
+fn compile(function: &str, pattern: &Value) -> Result<Regex, OperationError> {
+    match pattern {
+        Value::String(pattern) => compile_cached(pattern)
+            .map_err(|e| fail(format!("Invalid regex #\"{}\" for '{}': {}", pattern, function, e))),
+        _ => Err(fail(format!(
+            "'{}' needs a regex pattern, got {}",
+            function,
+            value_type_name(pattern)
+        ))),
+    }
+}
+
+fn string_arg<'v>(function: &str, value: &'v Value) -> Result<&'v str, OperationError> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(fail(format!(
+            "'{}' needs a string, got {}",
+            function,
+            value_type_name(value)
+        ))),
+    }
+}
+
+/// A match as Clojure reports it: the matched text when the pattern has no
+/// groups, and otherwise a vector of the match followed by each group, with
+/// nil for groups which did not take part.
+fn match_value(captures: &Captures<'_>) -> Value {
+    let group = |m: Option<regex::Match<'_>>| m.map_or(Value::Nil, |m| Value::from(m.as_str()));
+
+    if captures.len() == 1 {
+        group(captures.get(0))
+    } else {
+        Value::Vector(captures.iter().map(group).collect())
+    }
+}
+
+/// `test(re)`: whether the pattern matches anywhere in the input.
+crate fn test(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("test", &args[0])?;
+    Ok(Value::Boolean(re.is_match(text("test", &input)?)))
+}
+
+/// `re-find(re)`: the first match in the input, or nil.
+crate fn re_find(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("re-find", &args[0])?;
+    let s = text("re-find", &input)?;
+
+    Ok(re.captures(s).map_or(Value::Nil, |c| match_value(&c)))
+}
+
+/// `re-matches(re)`: the match if the pattern matches the whole input, or
+/// nil. The anchored copy of the pattern is cached like the pattern itself.
+crate fn re_matches(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("re-matches", &args[0])?;
+    let anchored = compile("re-matches", &Value::String(format!("^(?:{})$", re.as_str())))?;
+    let s = text("re-matches", &input)?;
+
+    Ok(anchored.captures(s).map_or(Value::Nil, |c| match_value(&c)))
+}
+
+/// `re-seq(re)`: a vector of every match in the input.
+crate fn re_seq(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("re-seq", &args[0])?;
+    let s = text("re-seq", &input)?;
+
+    Ok(Value::Vector(
+        re.captures_iter(s).map(|c| match_value(&c)).collect(),
+    ))
+}
+
+/// `capture(re)`: a map from the name of each named group of the first
+/// match, as a keyword, to the text it matched. Nil when nothing matches.
+crate fn capture(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("capture", &args[0])?;
+    let s = text("capture", &input)?;
+
+    let captures = match re.captures(s) {
+        Some(captures) => captures,
+        None => return Ok(Value::Nil),
+    };
+
+    let groups: Map = re
+        .capture_names()
+        .filter_map(|name| name)
+        .map(|name| {
+            let matched = captures
+                .name(name)
+                .map_or(Value::Nil, |m| Value::from(m.as_str()));
+            (Value::Keyword(name.to_owned()), matched)
+        })
+        .collect();
+
+    Ok(Value::Map(groups))
+}
+
+/// `replace(re; replacement)`: replaces the first match. The replacement may
+/// refer to groups as `$1` or `$name`.
+crate fn replace(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("replace", &args[0])?;
+    let replacement = string_arg("replace", &args[1])?;
+
+    Ok(Value::from(re.replace(text("replace", &input)?, replacement).as_ref()))
+}
+
+/// `replace-all(re; replacement)`: replaces every match.
+crate fn replace_all(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("replace-all", &args[0])?;
+    let replacement = string_arg("replace-all", &args[1])?;
+
+    Ok(Value::from(
+        re.replace_all(text("replace-all", &input)?, replacement).as_ref(),
+    ))
+}
+
+/// `split(re)`: the parts of the input between matches.
+crate fn split(input: Value, args: Vec<Value>) -> OperationResult {
+    let re = compile("split", &args[0])?;
+    let s = text("split", &input)?;
+
+    Ok(Value::Vector(re.split(s).map(Value::from).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: Vec<&str>) -> Value {
+        Value::Vector(items.into_iter().map(Value::from).collect())
+    }
+
+    #[test]
+    fn test_matching() {
+        let input = Value::from("v1.2 and v3.4");
+        let version = Value::from(r"v(\d+)\.(\d+)");
+
+        assert_eq!(
+            re_find(input.clone(), vec![version.clone()]).unwrap(),
+            strings(vec!["v1.2", "1", "2"])
+        );
+        assert_eq!(
+            re_seq(input.clone(), vec![Value::from(r"\d")]).unwrap(),
+            strings(vec!["1", "2", "3", "4"])
+        );
+        assert_eq!(re_matches(input.clone(), vec![version.clone()]).unwrap(), Value::Nil);
+        assert_eq!(
+            re_matches(Value::from("v1.2"), vec![version]).unwrap(),
+            strings(vec!["v1.2", "1", "2"])
+        );
+        assert_eq!(test(input, vec![Value::from("and")]).unwrap(), Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_capture() {
+        let captured = capture(
+            Value::from("db:5432"),
+            vec![Value::from(r"(?P<host>\w+):(?P<port>\d+)(?P<path>/.*)?")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            captured,
+            Value::Map(
+                vec![
+                    (Value::Keyword("host".to_owned()), Value::from("db")),
+                    (Value::Keyword("port".to_owned()), Value::from("5432")),
+                    (Value::Keyword("path".to_owned()), Value::Nil),
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn test_replace_and_split() {
+        let input = Value::from("a1b22c");
+
+        assert_eq!(
+            replace(input.clone(), vec![Value::from(r"\d+"), Value::from("-")]).unwrap(),
+            Value::from("a-b22c")
+        );
+        assert_eq!(
+            replace_all(input.clone(), vec![Value::from(r"(\d+)"), Value::from("<$1>")]).unwrap(),
+            Value::from("a<1>b<22>c")
+        );
+        assert_eq!(
+            split(input, vec![Value::from(r"\d+")]).unwrap(),
+            strings(vec!["a", "b", "c"])
+        );
+        assert!(test(Value::Nil, vec![Value::from("a")]).is_err());
+        assert!(test(Value::from("a"), vec![Value::from("(")]).is_err());
+    }
+
+    #[test]
+    fn test_compile_cache() {
+        let pattern = Value::from("c[a-z]+e");
+
+        assert_eq!(
+            re_matches(Value::from("cache"), vec![pattern]).unwrap(),
+            Value::from("cache")
+        );
+        COMPILED.with(|compiled| {
+            let compiled = compiled.borrow();
+            assert!(compiled.contains_key("c[a-z]+e"));
+            assert!(compiled.contains_key("^(?:c[a-z]+e)$"));
+        });
+
+        for idx in 0..CACHED_PATTERNS {
+            compile("test", &Value::from(format!("x{}", idx))).unwrap();
+        }
+        COMPILED.with(|compiled| assert!(compiled.borrow().len() <= CACHED_PATTERNS));
+    }
+}
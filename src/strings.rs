@@ -0,0 +1,260 @@
+use super::output::edn_string;
+use super::transform::{fail, text, value_type_name, OperationError, OperationResult};
+use super::value::Value;
+
+fn index(function: &str, value: &Value) -> Result<usize, OperationError> {
+    match value {
+        Value::Integer(i) if *i >= 0 => Ok(*i as usize),
+        _ => Err(fail(format!(
+            "'{}' needs a non-negative integer index, got {}",
+            function,
+            value_type_name(value)
+        ))),
+    }
+}
+
+/// The text `str` gives a value, as Clojure's `str` does: strings as they
+/// are, nil as the empty string and anything else as its EDN.
+fn to_text(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Nil => String::new(),
+        other => edn_string(other),
+    }
+}
+
+/// The name of a keyword or symbol, split from its namespace. A string is
+/// all name, as in Clojure.
+fn split_name<'v>(
+    function: &str,
+    value: &'v Value,
+) -> Result<(Option<&'v str>, &'v str), OperationError> {
+    let full = match value {
+        Value::Keyword(s) | Value::Symbol(s) => s.as_str(),
+        Value::String(s) => return Ok((None, s)),
+        _ => {
+            return Err(fail(format!(
+                "Can not apply '{}' to {}",
+                function,
+                value_type_name(value)
+            )))
+        }
+    };
+
+    match full.find('/') {
+        Some(idx) if idx > 0 && idx < full.len() - 1 => {
+            Ok((Some(&full[..idx]), &full[idx + 1..]))
+        }
+        _ => Ok((None, full)),
+    }
+}
+
+/// `upper-case`: the input with every letter in upper case.
+crate fn upper_case(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::String(text("upper-case", &input)?.to_uppercase()))
+}
+
+/// `lower-case`: the input with every letter in lower case.
+crate fn lower_case(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::String(text("lower-case", &input)?.to_lowercase()))
+}
+
+/// `trim`: removes whitespace from both ends.
+crate fn trim(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::from(text("trim", &input)?.trim()))
+}
+
+/// `blank?`: whether the input is nil, or a string of only whitespace.
+crate fn is_blank(input: Value, _args: Vec<Value>) -> OperationResult {
+    match input {
+        Value::Nil => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(text("blank?", &input)?.trim().is_empty())),
+    }
+}
+
+/// `starts-with?(s)`: whether the input begins with `s`.
+crate fn starts_with(input: Value, args: Vec<Value>) -> OperationResult {
+    let prefix = text("starts-with?", &args[0])?;
+    Ok(Value::Boolean(text("starts-with?", &input)?.starts_with(prefix)))
+}
+
+/// `ends-with?(s)`: whether the input ends with `s`.
+crate fn ends_with(input: Value, args: Vec<Value>) -> OperationResult {
+    let suffix = text("ends-with?", &args[0])?;
+    Ok(Value::Boolean(text("ends-with?", &input)?.ends_with(suffix)))
+}
+
+/// `includes?(s)`: whether `s` appears anywhere in the input.
+crate fn includes(input: Value, args: Vec<Value>) -> OperationResult {
+    let part = text("includes?", &args[0])?;
+    Ok(Value::Boolean(text("includes?", &input)?.contains(part)))
+}
+
+/// `subs(start)` or `subs(start; end)`: the characters from `start` up to
+/// `end`, or to the end of the input.
+crate fn subs(input: Value, args: Vec<Value>) -> OperationResult {
+    let s = text("subs", &input)?;
+    let len = s.chars().count();
+    let start = index("subs", &args[0])?;
+    let end = match args.get(1) {
+        Some(end) => index("subs", end)?,
+        None => len,
+    };
+
+    if start > end || end > len {
+        return Err(fail(format!(
+            "'subs' range {}..{} is out of bounds for a string of {} characters",
+            start, end, len
+        )));
+    }
+
+    Ok(Value::String(s.chars().skip(start).take(end - start).collect()))
+}
+
+/// `join` or `join(separator)`: the items of a collection as one string,
+/// each converted as by `str`.
+crate fn join(input: Value, args: Vec<Value>) -> OperationResult {
+    let separator = match args.get(0) {
+        Some(separator) => text("join", separator)?,
+        None => "",
+    };
+
+    let items: Vec<Value> = match input {
+        Value::List(items) | Value::Vector(items) => items,
+        Value::Set(items) => items.into_iter().collect(),
+        _ => {
+            return Err(fail(format!(
+                "Can not apply 'join' to {}",
+                value_type_name(&input)
+            )))
+        }
+    };
+
+    Ok(Value::String(
+        items
+            .into_iter()
+            .map(to_text)
+            .collect::<Vec<_>>()
+            .join(separator),
+    ))
+}
+
+/// `str`: the input as a string.
+crate fn str_of(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::String(to_text(input)))
+}
+
+/// A name for `keyword` or `symbol`, which must not be empty and must not
+/// hold whitespace or the characters which delimit EDN forms.
+fn valid_name(function: &str, name: &str) -> Result<String, OperationError> {
+    let is_delimiter = |c: char| c.is_whitespace() || "()[]{}\",;".contains(c);
+
+    if name.is_empty() || name.contains(is_delimiter) {
+        return Err(fail(format!(
+            "Invalid name {} for '{}'",
+            edn_string(Value::from(name)),
+            function
+        )));
+    }
+
+    Ok(name.to_owned())
+}
+
+/// `keyword`: a keyword with the name of a string, symbol or keyword. A
+/// leading `:` is dropped, so `":a"` and `"a"` both give `:a`.
+crate fn keyword(input: Value, _args: Vec<Value>) -> OperationResult {
+    match input {
+        Value::String(s) | Value::Symbol(s) | Value::Keyword(s) => {
+            let name = if s.starts_with(':') { &s[1..] } else { s.as_str() };
+            Ok(Value::Keyword(valid_name("keyword", name)?))
+        }
+        _ => Err(fail(format!(
+            "Can not apply 'keyword' to {}",
+            value_type_name(&input)
+        ))),
+    }
+}
+
+/// `symbol`: a symbol with the name of a string, keyword or symbol.
+crate fn symbol(input: Value, _args: Vec<Value>) -> OperationResult {
+    match input {
+        Value::String(s) | Value::Keyword(s) | Value::Symbol(s) => {
+            Ok(Value::Symbol(valid_name("symbol", &s)?))
+        }
+        _ => Err(fail(format!(
+            "Can not apply 'symbol' to {}",
+            value_type_name(&input)
+        ))),
+    }
+}
+
+/// `name`: the name of a keyword or symbol without its namespace, or a string
+/// as it is.
+crate fn name(input: Value, _args: Vec<Value>) -> OperationResult {
+    let (_, name) = split_name("name", &input)?;
+    Ok(Value::from(name))
+}
+
+/// `namespace`: the namespace of a keyword or symbol, or nil.
+crate fn namespace(input: Value, _args: Vec<Value>) -> OperationResult {
+    let (namespace, _) = split_name("namespace", &input)?;
+    Ok(namespace.map_or(Value::Nil, Value::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Function = fn(Value, Vec<Value>) -> OperationResult;
+
+    fn run(function: Function, input: &str, args: Vec<Value>) -> Value {
+        function(Value::from(input), args).unwrap()
+    }
+
+    #[test]
+    fn test_case_and_trim() {
+        assert_eq!(run(upper_case, "abc", vec![]), Value::from("ABC"));
+        assert_eq!(run(lower_case, "ÀB", vec![]), Value::from("àb"));
+        assert_eq!(run(trim, "  a b\n", vec![]), Value::from("a b"));
+        assert_eq!(run(is_blank, " \t", vec![]), Value::Boolean(true));
+        assert_eq!(is_blank(Value::Nil, vec![]).unwrap(), Value::Boolean(true));
+        assert!(upper_case(Value::Integer(1), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_predicates_and_subs() {
+        let prod = vec![Value::from("prod")];
+
+        assert_eq!(run(starts_with, "prod-db", prod.clone()), Value::Boolean(true));
+        assert_eq!(run(ends_with, "prod-db", prod), Value::Boolean(false));
+        assert_eq!(run(includes, "prod-db", vec![Value::from("d-d")]), Value::Boolean(true));
+        assert_eq!(
+            run(subs, "héllo", vec![Value::Integer(1), Value::Integer(3)]),
+            Value::from("él")
+        );
+        assert_eq!(run(subs, "héllo", vec![Value::Integer(3)]), Value::from("lo"));
+        assert!(subs(Value::from("a"), vec![Value::Integer(2)]).is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        let kw = |name: &str| Value::Keyword(name.to_owned());
+        let items = Value::Vector(vec![Value::from("a"), Value::Integer(1), Value::Nil, kw("k")]);
+
+        assert_eq!(join(items, vec![Value::from(",")]).unwrap(), Value::from("a,1,,:k"));
+        assert_eq!(str_of(kw("a/b"), vec![]).unwrap(), Value::from(":a/b"));
+        assert_eq!(run(keyword, "a/b", vec![]), kw("a/b"));
+        assert_eq!(run(keyword, ":a", vec![]), kw("a"));
+        assert_eq!(run(symbol, "b", vec![]), Value::Symbol("b".to_owned()));
+        for invalid in &["", ":", "a b", "a\tb", "a,b", "(a)", "a\"", "x;y", "{k}"] {
+            assert!(keyword(Value::from(*invalid), vec![]).is_err(), "{:?}", invalid);
+        }
+        assert!(symbol(Value::from("a b"), vec![]).is_err());
+        assert!(symbol(Value::from(""), vec![]).is_err());
+        assert_eq!(name(kw("a/b"), vec![]).unwrap(), Value::from("b"));
+        assert_eq!(namespace(Value::Symbol("a/b".to_owned()), vec![]).unwrap(), Value::from("a"));
+        assert_eq!(namespace(Value::Symbol("/".to_owned()), vec![]).unwrap(), Value::Nil);
+        assert_eq!(run(name, "a/b", vec![]), Value::from("a/b"));
+        assert_eq!(run(namespace, "a/b", vec![]), Value::Nil);
+    }
+}
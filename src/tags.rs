@@ -0,0 +1,433 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+
+use super::output::edn_string;
+use super::transform::{value_type_name, OperationError, OperationResult};
+use super::value::Value;
+
+/// What a tagged literal must wrap for its tag to be valid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate enum TagKind {
+    /// An RFC 3339 timestamp string, as in `#inst "2026-01-01T00:00:00Z"`.
+    Inst,
+    /// A UUID string, as in `#uuid "f81d4fae-7dec-11d0-a765-00a0c91e6bf6"`.
+    Uuid,
+    String,
+    Integer,
+    Number,
+    Keyword,
+    Symbol,
+    Vector,
+    Map,
+    Any,
+}
+
+impl FromStr for TagKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TagKind, String> {
+        match s {
+            "inst" => Ok(TagKind::Inst),
+            "uuid" => Ok(TagKind::Uuid),
+            "string" => Ok(TagKind::String),
+            "integer" => Ok(TagKind::Integer),
+            "number" => Ok(TagKind::Number),
+            "keyword" => Ok(TagKind::Keyword),
+            "symbol" => Ok(TagKind::Symbol),
+            "vector" => Ok(TagKind::Vector),
+            "map" => Ok(TagKind::Map),
+            "any" => Ok(TagKind::Any),
+            _ => Err(format!(
+                "Unknown tag kind '{}' (expected one of inst, uuid, string, integer, number, \
+                 keyword, symbol, vector, map, any)",
+                s
+            )),
+        }
+    }
+}
+
+impl TagKind {
+    fn description(self) -> &'static str {
+        match self {
+            TagKind::Inst => "an RFC 3339 timestamp string",
+            TagKind::Uuid => "a UUID string",
+            TagKind::String => "a string",
+            TagKind::Integer => "an integer",
+            TagKind::Number => "a number",
+            TagKind::Keyword => "a keyword",
+            TagKind::Symbol => "a symbol",
+            TagKind::Vector => "a vector",
+            TagKind::Map => "a map",
+            TagKind::Any => "any value",
+        }
+    }
+
+    fn accepts(self, value: &Value) -> bool {
+        match (self, value) {
+            (TagKind::Inst, Value::String(s)) => parse_inst(s).is_some(),
+            (TagKind::Uuid, Value::String(s)) => is_uuid(s),
+            (TagKind::String, Value::String(_))
+            | (TagKind::Integer, Value::Integer(_))
+            | (TagKind::Integer, Value::BigInt(_))
+            | (TagKind::Number, Value::Integer(_))
+            | (TagKind::Number, Value::BigInt(_))
+            | (TagKind::Number, Value::Float(_))
+            | (TagKind::Number, Value::BigDecimal(_))
+            | (TagKind::Number, Value::Ratio(_))
+            | (TagKind::Keyword, Value::Keyword(_))
+            | (TagKind::Symbol, Value::Symbol(_))
+            | (TagKind::Vector, Value::Vector(_))
+            | (TagKind::Map, Value::Map(_))
+            | (TagKind::Any, _) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+crate struct TagError {
+    crate tag: String,
+    crate message: String,
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}: {}", self.tag, self.message)
+    }
+}
+
+/// The tags eq knows how to validate. `#inst` and `#uuid` are always
+/// registered; more can be declared with `--tag name=kind`. Tags which are
+/// not registered are passed through untouched, as EDN requires.
+#[derive(Debug, Clone)]
+crate struct TagRegistry {
+    kinds: BTreeMap<String, TagKind>,
+}
+
+impl Default for TagRegistry {
+    fn default() -> Self {
+        let mut kinds = BTreeMap::new();
+        kinds.insert("inst".to_owned(), TagKind::Inst);
+        kinds.insert("uuid".to_owned(), TagKind::Uuid);
+
+        TagRegistry { kinds }
+    }
+}
+
+impl TagRegistry {
+    /// Registers a tag from a `name=kind` declaration, such as
+    /// `my/tag=string`.
+    crate fn declare(&mut self, declaration: &str) -> Result<(), String> {
+        let mut parts = declaration.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim_left_matches('#');
+        let kind = match parts.next() {
+            Some(kind) => kind.parse::<TagKind>()?,
+            None => return Err(format!("Expected name=kind, got '{}'", declaration)),
+        };
+
+        if name.is_empty() {
+            return Err(format!("Missing tag name in '{}'", declaration));
+        }
+
+        self.kinds.insert(name.to_owned(), kind);
+        Ok(())
+    }
+
+    /// Checks every registered tag within a value wraps what it should.
+    crate fn validate(&self, value: &Value) -> Result<(), TagError> {
+        match value {
+            Value::List(items) | Value::Vector(items) => {
+                items.iter().map(|item| self.validate(item)).collect()
+            }
+            Value::Set(items) => items.iter().map(|item| self.validate(item)).collect(),
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| self.validate(k).and_then(|_| self.validate(v)))
+                .collect(),
+            Value::Tagged(tag, inner) => {
+                if let Some(kind) = self.kinds.get(tag) {
+                    if !kind.accepts(inner) {
+                        return Err(TagError {
+                            tag: tag.clone(),
+                            message: format!(
+                                "expected {}, got {}",
+                                kind.description(),
+                                edn_string((**inner).clone())
+                            ),
+                        });
+                    }
+                }
+                self.validate(inner)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses exactly `len` decimal digits.
+fn fixed_digits(s: &str, len: usize) -> Option<u32> {
+    if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Splits the offset (`Z`, `+01:00` or `-05:30`) off the end of an instant,
+/// taking an instant without one to be in UTC.
+fn split_offset(s: &str) -> Option<(&str, FixedOffset)> {
+    if s.ends_with('Z') {
+        return Some((&s[..s.len() - 1], FixedOffset::east(0)));
+    }
+
+    let at = s.len().saturating_sub(6);
+    let sign = match s.as_bytes().get(at) {
+        Some(b'+') => 1,
+        Some(b'-') => -1,
+        _ => return Some((s, FixedOffset::east(0))),
+    };
+
+    if s.as_bytes().get(at + 3) != Some(&b':') {
+        return Some((s, FixedOffset::east(0)));
+    }
+
+    let hours = fixed_digits(s.get(at + 1..at + 3)?, 2)? as i32;
+    let minutes = fixed_digits(s.get(at + 4..)?, 2)? as i32;
+    let offset = FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?;
+
+    Some((&s[..at], offset))
+}
+
+/// Parses the string of an `#inst`. Besides full RFC 3339 timestamps this
+/// accepts the partial forms EDN allows, where any trailing fields may be
+/// left out: `2026`, `2026-01`, `2026-01-01T10`, `2026-01-01T10:00Z` and so
+/// on. Missing fields are taken to be the start of the period, and a
+/// missing offset to be UTC.
+crate fn parse_inst(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(instant) = DateTime::parse_from_rfc3339(s) {
+        return Some(instant);
+    }
+
+    let (local, offset) = split_offset(s)?;
+    let mut parts = local.splitn(2, 'T');
+    let date: Vec<&str> = parts.next()?.split('-').collect();
+    let time = parts.next();
+
+    if date.len() > 3 || (time.is_some() && date.len() < 3) {
+        return None;
+    }
+
+    let year = fixed_digits(date[0], 4)? as i32;
+    let month = date.get(1).map_or(Some(1), |m| fixed_digits(m, 2))?;
+    let day = date.get(2).map_or(Some(1), |d| fixed_digits(d, 2))?;
+
+    let mut clock = [0; 3];
+    let mut nanos = 0;
+
+    if let Some(time) = time {
+        let mut parts = time.splitn(2, '.');
+        let fields: Vec<&str> = parts.next()?.split(':').collect();
+
+        if fields.len() > 3 {
+            return None;
+        }
+        for (field, value) in fields.iter().zip(clock.iter_mut()) {
+            *value = fixed_digits(field, 2)?;
+        }
+
+        if let Some(fraction) = parts.next() {
+            let digits = fraction.bytes().all(|b| b.is_ascii_digit());
+            if fields.len() < 3 || fraction.is_empty() || !digits {
+                return None;
+            }
+            nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse().ok()?;
+        }
+    }
+
+    let naive = NaiveDate::from_ymd_opt(year, month, day)?
+        .and_hms_nano_opt(clock[0], clock[1], clock[2], nanos)?;
+
+    offset.from_local_datetime(&naive).single()
+}
+
+/// The instant an `#inst` value stands for, if it is one.
+crate fn inst_value(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Tagged(tag, inner) if tag == "inst" => match &**inner {
+            Value::String(s) => parse_inst(s).map(|instant| instant.with_timezone(&Utc)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether a string is a UUID in the usual 8-4-4-4-12 hex digit form.
+crate fn is_uuid(s: &str) -> bool {
+    let groups: Vec<&str> = s.split('-').collect();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip(&[8, 4, 4, 4, 12])
+            .all(|(group, len)| {
+                group.len() == *len && group.chars().all(|c| c.is_ascii_hexdigit())
+            })
+}
+
+/// Compares two tagged values by what they mean rather than how they are
+/// written: instants chronologically, whatever their offsets. `None` when the
+/// values are not both instants.
+crate fn compare_tagged(a: &Value, b: &Value) -> Option<Ordering> {
+    Some(inst_value(a)?.cmp(&inst_value(b)?))
+}
+
+/// The tag name given to `tag(name)` or `retag`, as a string, keyword or
+/// symbol. A leading `#` is ignored, so `"#inst"` and `:inst` are the same.
+fn tag_name(function: &str, value: &Value) -> Result<String, OperationError> {
+    match value {
+        Value::String(name) | Value::Keyword(name) | Value::Symbol(name)
+            if !name.trim_left_matches('#').is_empty() =>
+        {
+            Ok(name.trim_left_matches('#').to_owned())
+        }
+        _ => Err(OperationError::Failed(format!(
+            "'{}' needs a tag name, got {}",
+            function,
+            value_type_name(value)
+        ))),
+    }
+}
+
+fn rename_tags(value: Value, from: &str, to: &str) -> Value {
+    let rename = |value| rename_tags(value, from, to);
+
+    match value {
+        Value::List(items) => Value::List(items.into_iter().map(rename).collect()),
+        Value::Vector(items) => Value::Vector(items.into_iter().map(rename).collect()),
+        Value::Set(items) => Value::Set(items.into_iter().map(rename).collect()),
+        Value::Map(m) => Value::Map(m.into_iter().map(|(k, v)| (rename(k), rename(v))).collect()),
+        Value::Tagged(tag, inner) => {
+            let tag = if tag == from { to.to_owned() } else { tag };
+            Value::Tagged(tag, Box::new(rename(*inner)))
+        }
+        other => other,
+    }
+}
+
+/// `tag`: the name of the input's tag as a string, or nil if it has none.
+crate fn tag_of(input: Value, _args: Vec<Value>) -> OperationResult {
+    match input {
+        Value::Tagged(tag, _) => Ok(Value::String(tag)),
+        _ => Ok(Value::Nil),
+    }
+}
+
+/// `tag(name)`: wraps the input in a tag.
+crate fn tag(input: Value, args: Vec<Value>) -> OperationResult {
+    Ok(Value::Tagged(tag_name("tag", &args[0])?, Box::new(input)))
+}
+
+/// `untag`: the value inside a tagged value. Untagged input is passed through.
+crate fn untag(input: Value, _args: Vec<Value>) -> OperationResult {
+    match input {
+        Value::Tagged(_, inner) => Ok(*inner),
+        other => Ok(other),
+    }
+}
+
+/// `retag(from; to)`: renames every `from` tag to `to`, at any depth.
+crate fn retag(input: Value, args: Vec<Value>) -> OperationResult {
+    let from = tag_name("retag", &args[0])?;
+    let to = tag_name("retag", &args[1])?;
+
+    Ok(rename_tags(input, &from, &to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(tag: &str, inner: Value) -> Value {
+        Value::Tagged(tag.to_owned(), Box::new(inner))
+    }
+
+    #[test]
+    fn test_parse_inst() {
+        let midnight = parse_inst("2026-01-01T00:00:00Z").unwrap();
+
+        assert_eq!(parse_inst("2026-01-01"), Some(midnight));
+        assert_eq!(parse_inst("2026-01-01T00:00:00.000"), Some(midnight));
+        assert_eq!(parse_inst("2026-01-01T01:00:00+01:00"), Some(midnight));
+        assert_eq!(parse_inst("2026"), Some(midnight));
+        assert_eq!(parse_inst("2026-01"), Some(midnight));
+
+        let ten = parse_inst("2026-01-01T10:00:00Z").unwrap();
+        assert_eq!(parse_inst("2026-01-01T10"), Some(ten));
+        assert_eq!(parse_inst("2026-01-01T10:00Z"), Some(ten));
+        assert_eq!(parse_inst("2026-01-01T11:00+01:00"), Some(ten));
+
+        let fractional = parse_inst("2026-01-01T10:00:00.5-00:30").unwrap();
+        assert_eq!(fractional.timestamp_subsec_millis(), 500);
+
+        assert_eq!(parse_inst("yesterday"), None);
+        assert_eq!(parse_inst("2026-1"), None);
+        assert_eq!(parse_inst("2026-01T10"), None);
+        assert_eq!(parse_inst("2026-02-30"), None);
+        assert_eq!(parse_inst("2026-01-01T10:00.5"), None);
+    }
+
+    #[test]
+    fn test_compare_tagged() {
+        let earlier = tagged("inst", Value::from("2026-01-01T10:00:00+02:00"));
+        let later = tagged("inst", Value::from("2026-01-01T09:00:00Z"));
+
+        assert_eq!(compare_tagged(&earlier, &later), Some(Ordering::Less));
+        assert_eq!(compare_tagged(&earlier, &Value::Nil), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut registry = TagRegistry::default();
+        registry.declare("my/tag=integer").unwrap();
+        assert!(registry.declare("my/tag=date").is_err());
+
+        let valid = Value::Vector(vec![
+            tagged("uuid", Value::from("f81d4fae-7dec-11d0-a765-00a0c91e6bf6")),
+            tagged("my/tag", Value::Integer(1)),
+            tagged("other", Value::Nil),
+        ]);
+        assert!(registry.validate(&valid).is_ok());
+
+        let invalid = Value::Vector(vec![tagged("my/tag", Value::from("1"))]);
+        let error = registry.validate(&invalid).unwrap_err();
+        assert_eq!(error.tag, "my/tag");
+        assert!(error.message.ends_with("got \"1\""), "{}", error.message);
+        assert!(registry.validate(&tagged("inst", Value::from("soon"))).is_err());
+    }
+
+    #[test]
+    fn test_tag_functions() {
+        let money = tagged("myapp/money", Value::Integer(5));
+        let document = Value::Vector(vec![money.clone(), tagged("other", money.clone())]);
+
+        assert_eq!(tag_of(money.clone(), vec![]).unwrap(), Value::from("myapp/money"));
+        assert_eq!(tag_of(Value::Integer(5), vec![]).unwrap(), Value::Nil);
+        assert_eq!(untag(money.clone(), vec![]).unwrap(), Value::Integer(5));
+        assert_eq!(
+            tag(Value::Integer(5), vec![Value::Keyword("myapp/money".to_owned())]).unwrap(),
+            money
+        );
+        assert_eq!(
+            retag(document, vec![Value::from("#myapp/money"), Value::from("money")]).unwrap(),
+            Value::Vector(vec![
+                tagged("money", Value::Integer(5)),
+                tagged("other", tagged("money", Value::Integer(5))),
+            ])
+        );
+        assert!(tag(Value::Nil, vec![Value::Integer(1)]).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+use std::fmt;
+
+use colored::control::SHOULD_COLORIZE;
+
+use super::input;
+use super::output::edn_string;
+use super::value::Value;
+
+#[derive(Debug)]
+crate struct ThemeError(String);
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A terminal foreground color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate enum ThemeColor {
+    /// One of the 16 standard colors, stored as its SGR foreground code.
+    Basic(u8),
+    /// An entry in the 256-color palette.
+    Ansi256(u8),
+    /// A 24-bit truecolor value.
+    Rgb(u8, u8, u8),
+}
+
+const BASIC_COLORS: [(&str, u8); 16] = [
+    ("black", 30),
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("white", 37),
+    ("bright-black", 90),
+    ("bright-red", 91),
+    ("bright-green", 92),
+    ("bright-yellow", 93),
+    ("bright-blue", 94),
+    ("bright-magenta", 95),
+    ("bright-cyan", 96),
+    ("bright-white", 97),
+];
+
+impl ThemeColor {
+    fn named(name: &str) -> Option<ThemeColor> {
+        BASIC_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, code)| ThemeColor::Basic(*code))
+    }
+
+    /// Parses `#rrggbb`.
+    fn hex(hex: &str) -> Option<ThemeColor> {
+        if hex.len() != 7 || !hex.starts_with('#') {
+            return None;
+        }
+
+        let channel = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok();
+        Some(ThemeColor::Rgb(channel(1)?, channel(3)?, channel(5)?))
+    }
+
+    fn sgr(self) -> String {
+        match self {
+            ThemeColor::Basic(code) => code.to_string(),
+            ThemeColor::Ansi256(n) => format!("38;5;{}", n),
+            ThemeColor::Rgb(r, g, b) => format!("38;2;{};{};{}", r, g, b),
+        }
+    }
+}
+
+/// How a single kind of element is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+crate struct Style {
+    color: Option<ThemeColor>,
+    bold: bool,
+    dim: bool,
+}
+
+impl Style {
+    fn plain() -> Style {
+        Style::default()
+    }
+
+    fn color(color: ThemeColor) -> Style {
+        Style {
+            color: Some(color),
+            ..Style::default()
+        }
+    }
+
+    fn basic(name: &str) -> Style {
+        Style::color(ThemeColor::named(name).unwrap())
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> Style {
+        Style::color(ThemeColor::Rgb(r, g, b))
+    }
+
+    fn bold(self) -> Style {
+        Style { bold: true, ..self }
+    }
+
+    fn dim(self) -> Style {
+        Style { dim: true, ..self }
+    }
+
+    fn sgr(self) -> Option<String> {
+        let mut codes: Vec<String> = vec![];
+
+        if self.bold {
+            codes.push("1".to_owned());
+        }
+        if self.dim {
+            codes.push("2".to_owned());
+        }
+        if let Some(color) = self.color {
+            codes.push(color.sgr());
+        }
+
+        if codes.is_empty() {
+            None
+        } else {
+            Some(codes.join(";"))
+        }
+    }
+
+    crate fn paint(self, text: impl Into<String>) -> Painted {
+        Painted {
+            text: text.into(),
+            style: self,
+        }
+    }
+}
+
+/// Text together with the style it should be written in. Formatting it
+/// emits ANSI escapes only when colored output is enabled.
+crate struct Painted {
+    text: String,
+    style: Style,
+}
+
+impl Painted {
+    crate fn plain(text: impl Into<String>) -> Painted {
+        Style::plain().paint(text)
+    }
+
+    /// The text without any escape sequences.
+    crate fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for Painted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.style.sgr() {
+            Some(ref codes) if SHOULD_COLORIZE.should_colorize() => {
+                write!(f, "\x1b[{}m{}\x1b[0m", codes, self.text)
+            }
+            _ => write!(f, "{}", self.text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+crate struct ColorTheme {
+    crate nil: Style,
+    crate boolean: Style,
+    crate keyword: Style,
+    crate char: Style,
+    crate string: Style,
+    crate number: Style,
+    crate tag: Style,
+    crate symbol: Style,
+    crate vector: Style,
+    crate list: Style,
+    crate map: Style,
+    crate set: Style,
+    /// Markers for the entries of a structural diff.
+    crate added: Style,
+    crate removed: Style,
+    crate changed: Style,
+    /// Bracket colors for rainbow output, indexed by nesting depth and
+    /// cycled once the depth exceeds its length.
+    crate rainbow: Vec<Style>,
+}
+
+crate const BUILTIN_THEMES: [&str; 4] = ["default", "solarized-dark", "light", "monochrome"];
+
+impl Default for ColorTheme {
+    fn default() -> Self {
+        ColorTheme {
+            nil: Style::basic("bright-blue"),
+            symbol: Style::basic("cyan"),
+            boolean: Style::basic("magenta"),
+            char: Style::basic("bright-red"),
+            string: Style::basic("yellow"),
+            number: Style::basic("green"),
+            keyword: Style::basic("red"),
+            tag: Style::basic("bright-green"),
+            vector: Style::basic("bright-yellow"),
+            list: Style::basic("bright-yellow"),
+            map: Style::basic("white"),
+            set: Style::basic("white"),
+            added: Style::basic("green").bold(),
+            removed: Style::basic("red").bold(),
+            changed: Style::basic("yellow").bold(),
+            rainbow: vec![
+                Style::basic("bright-yellow"),
+                Style::basic("bright-magenta"),
+                Style::basic("bright-cyan"),
+                Style::basic("bright-green"),
+                Style::basic("bright-blue"),
+                Style::basic("bright-red"),
+            ],
+        }
+    }
+}
+
+impl ColorTheme {
+    fn solarized_dark() -> Self {
+        ColorTheme {
+            nil: Style::rgb(0x6c, 0x71, 0xc4),
+            symbol: Style::rgb(0x26, 0x8b, 0xd2),
+            boolean: Style::rgb(0xd3, 0x36, 0x82),
+            char: Style::rgb(0xcb, 0x4b, 0x16),
+            string: Style::rgb(0x2a, 0xa1, 0x98),
+            number: Style::rgb(0x85, 0x99, 0x00),
+            keyword: Style::rgb(0xb5, 0x89, 0x00),
+            tag: Style::rgb(0xdc, 0x32, 0x2f),
+            vector: Style::rgb(0x93, 0xa1, 0xa1),
+            list: Style::rgb(0x93, 0xa1, 0xa1),
+            map: Style::rgb(0x93, 0xa1, 0xa1),
+            set: Style::rgb(0x93, 0xa1, 0xa1),
+            added: Style::rgb(0x85, 0x99, 0x00),
+            removed: Style::rgb(0xdc, 0x32, 0x2f),
+            changed: Style::rgb(0xb5, 0x89, 0x00),
+            rainbow: vec![
+                Style::rgb(0xb5, 0x89, 0x00),
+                Style::rgb(0xcb, 0x4b, 0x16),
+                Style::rgb(0xd3, 0x36, 0x82),
+                Style::rgb(0x6c, 0x71, 0xc4),
+                Style::rgb(0x26, 0x8b, 0xd2),
+                Style::rgb(0x2a, 0xa1, 0x98),
+                Style::rgb(0x85, 0x99, 0x00),
+            ],
+        }
+    }
+
+    fn light() -> Self {
+        ColorTheme {
+            nil: Style::basic("blue"),
+            symbol: Style::basic("blue"),
+            boolean: Style::basic("magenta"),
+            char: Style::basic("red"),
+            string: Style::basic("green"),
+            number: Style::basic("cyan"),
+            keyword: Style::basic("red").bold(),
+            tag: Style::basic("magenta"),
+            vector: Style::basic("black"),
+            list: Style::basic("black"),
+            map: Style::basic("black"),
+            set: Style::basic("black"),
+            added: Style::basic("green"),
+            removed: Style::basic("red"),
+            changed: Style::basic("blue"),
+            rainbow: vec![
+                Style::basic("blue"),
+                Style::basic("magenta"),
+                Style::basic("green"),
+                Style::basic("red"),
+            ],
+        }
+    }
+
+    fn monochrome() -> Self {
+        ColorTheme {
+            nil: Style::plain(),
+            symbol: Style::plain(),
+            boolean: Style::plain(),
+            char: Style::plain(),
+            string: Style::plain(),
+            number: Style::plain(),
+            keyword: Style::plain().bold(),
+            tag: Style::plain(),
+            vector: Style::plain(),
+            list: Style::plain(),
+            map: Style::plain(),
+            set: Style::plain(),
+            added: Style::plain().bold(),
+            removed: Style::plain().bold(),
+            changed: Style::plain().bold(),
+            rainbow: vec![Style::plain().bold(), Style::plain().dim()],
+        }
+    }
+
+    /// A theme which writes no escapes at all, for output that must be
+    /// byte-for-byte stable.
+    crate fn plain() -> Self {
+        ColorTheme {
+            nil: Style::plain(),
+            symbol: Style::plain(),
+            boolean: Style::plain(),
+            char: Style::plain(),
+            string: Style::plain(),
+            number: Style::plain(),
+            keyword: Style::plain(),
+            tag: Style::plain(),
+            vector: Style::plain(),
+            list: Style::plain(),
+            map: Style::plain(),
+            set: Style::plain(),
+            rainbow: vec![],
+            added: Style::plain(),
+            removed: Style::plain(),
+            changed: Style::plain(),
+        }
+    }
+
+    crate fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(ColorTheme::default()),
+            "solarized-dark" => Some(ColorTheme::solarized_dark()),
+            "light" => Some(ColorTheme::light()),
+            "monochrome" => Some(ColorTheme::monochrome()),
+            _ => None,
+        }
+    }
+
+    /// The style for brackets at the given nesting depth, when the theme has a
+    /// rainbow palette.
+    crate fn rainbow_at(&self, depth: usize) -> Option<Style> {
+        if self.rainbow.is_empty() {
+            None
+        } else {
+            Some(self.rainbow[depth % self.rainbow.len()])
+        }
+    }
+
+    fn element_mut(&mut self, name: &str) -> Option<&mut Style> {
+        Some(match name {
+            "nil" => &mut self.nil,
+            "boolean" => &mut self.boolean,
+            "keyword" => &mut self.keyword,
+            "char" => &mut self.char,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "tag" => &mut self.tag,
+            "symbol" => &mut self.symbol,
+            "vector" => &mut self.vector,
+            "list" => &mut self.list,
+            "map" => &mut self.map,
+            "set" => &mut self.set,
+            "added" => &mut self.added,
+            "removed" => &mut self.removed,
+            "changed" => &mut self.changed,
+            _ => return None,
+        })
+    }
+}
+
+fn parse_color(value: &Value) -> Result<ThemeColor, ThemeError> {
+    match value {
+        Value::Keyword(name) | Value::String(name) if !name.starts_with('#') => {
+            ThemeColor::named(name)
+                .ok_or_else(|| ThemeError(format!("Unknown color '{}'", name)))
+        }
+        Value::String(hex) => {
+            ThemeColor::hex(hex).ok_or_else(|| ThemeError(format!("Invalid color '{}'", hex)))
+        }
+        Value::Integer(n) if *n >= 0 && *n <= 255 => Ok(ThemeColor::Ansi256(*n as u8)),
+        _ => Err(ThemeError(format!("Invalid color {}", edn_string(value.clone())))),
+    }
+}
+
+/// Parses a style, which is either a bare color (`:red`, `208`, `"#ff8800"`),
+/// a vector of a color and modifiers (`[:red :bold]`), or a map
+/// (`{:color :red :bold true :dim false}`).
+fn parse_style(value: &Value) -> Result<Style, ThemeError> {
+    match value {
+        Value::Nil => Ok(Style::plain()),
+        Value::Vector(items) => items.iter().try_fold(
+            Style::plain(),
+            |style, item| -> Result<Style, ThemeError> {
+                match item {
+                    Value::Keyword(k) if k == "bold" => Ok(Style { bold: true, ..style }),
+                    Value::Keyword(k) if k == "dim" => Ok(Style { dim: true, ..style }),
+                    color => Ok(Style {
+                        color: Some(parse_color(color)?),
+                        ..style
+                    }),
+                }
+            },
+        ),
+        Value::Map(entries) => {
+            let flag = |name: &str| {
+                entries.get(&Value::Keyword(name.to_owned())) == Some(&Value::Boolean(true))
+            };
+
+            Ok(Style {
+                color: match entries.get(&Value::Keyword("color".to_owned())) {
+                    Some(color) => Some(parse_color(color)?),
+                    None => None,
+                },
+                bold: flag("bold"),
+                dim: flag("dim"),
+            })
+        }
+        color => Ok(Style::color(parse_color(color)?)),
+    }
+}
+
+/// Loads a theme from an EDN map of element names to styles, plus an optional
+/// `:rainbow` vector of bracket styles. Elements which are not mentioned keep
+/// their default style.
+crate fn load_theme(path: &str) -> Result<ColorTheme, ThemeError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ThemeError(format!("Could not read theme {}: {}", path, e)))?;
+    let forms = input::parse_edn(&contents).map_err(|e| {
+        ThemeError(format!("Could not parse theme {}: {}", path, e.describe(&contents)))
+    })?;
+
+    let entries = match forms.into_iter().next() {
+        Some(Value::Map(m)) => m,
+        _ => return Err(ThemeError(format!("Theme {} must be an EDN map", path))),
+    };
+
+    let mut theme = ColorTheme::default();
+    for (key, value) in entries.iter() {
+        let name = match key {
+            Value::Keyword(k) => k,
+            _ => {
+                return Err(ThemeError(format!(
+                    "Invalid theme key {}",
+                    edn_string(key.clone())
+                )))
+            }
+        };
+
+        if name == "rainbow" {
+            theme.rainbow = match value {
+                Value::Vector(styles) => styles
+                    .iter()
+                    .map(parse_style)
+                    .collect::<Result<_, _>>()?,
+                _ => {
+                    return Err(ThemeError(
+                        "Theme :rainbow must be a vector of styles".to_owned(),
+                    ))
+                }
+            };
+            continue;
+        }
+
+        match theme.element_mut(name) {
+            Some(style) => *style = parse_style(value)?,
+            None => return Err(ThemeError(format!("Unknown theme element :{}", name))),
+        }
+    }
+
+    Ok(theme)
+}
+
+/// Resolves `--theme`, which names either a built-in theme or a theme file.
+crate fn resolve_theme(name_or_path: &str) -> Result<ColorTheme, ThemeError> {
+    match ColorTheme::builtin(name_or_path) {
+        Some(theme) => Ok(theme),
+        None => load_theme(name_or_path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Value {
+        input::parse_edn(s).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_parse_style() {
+        assert_eq!(
+            parse_style(&parse(":bright-red")).unwrap(),
+            Style::color(ThemeColor::Basic(91))
+        );
+        assert_eq!(
+            parse_style(&parse("[208 :bold]")).unwrap(),
+            Style::color(ThemeColor::Ansi256(208)).bold()
+        );
+        assert_eq!(
+            parse_style(&parse("{:color \"#0a0B0c\" :dim true}")).unwrap(),
+            Style {
+                color: Some(ThemeColor::Rgb(10, 11, 12)),
+                bold: false,
+                dim: true,
+            }
+        );
+        assert!(parse_style(&parse(":no-such-color")).is_err());
+        assert_eq!(
+            parse_style(&parse("[:bold 1.5]")).unwrap_err().to_string(),
+            "Invalid color 1.5"
+        );
+    }
+
+    #[test]
+    fn test_rainbow_at() {
+        let theme = ColorTheme::light();
+        assert_eq!(theme.rainbow_at(1), Some(Style::basic("magenta")));
+        assert_eq!(theme.rainbow_at(5), Some(Style::basic("magenta")));
+
+        let empty = ColorTheme {
+            rainbow: vec![],
+            ..ColorTheme::default()
+        };
+        assert_eq!(empty.rainbow_at(0), None);
+    }
+
+    #[test]
+    fn test_sgr() {
+        assert_eq!(Style::plain().sgr(), None);
+        assert_eq!(
+            Style::color(ThemeColor::Rgb(1, 2, 3)).bold().sgr(),
+            Some("1;38;2;1;2;3".to_owned())
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike, SecondsFormat, TimeZone, Timelike, Utc};
+
+use super::tags;
+use super::transform::{fail, value_type_name, OperationError, OperationResult};
+use super::value::Value;
+
+/// The `#inst` for an instant, written in UTC with millisecond precision.
+crate fn inst_of(instant: DateTime<Utc>) -> Value {
+    Value::Tagged(
+        "inst".to_owned(),
+        Box::new(Value::String(
+            instant.to_rfc3339_opts(SecondsFormat::Millis, true),
+        )),
+    )
+}
+
+fn instant(name: &str, value: &Value) -> Result<DateTime<Utc>, OperationError> {
+    tags::inst_value(value).ok_or_else(|| {
+        fail(format!(
+            "Can not apply '{}' to {}",
+            name,
+            value_type_name(value)
+        ))
+    })
+}
+
+fn from_millis_value(name: &str, value: &Value) -> OperationResult {
+    match value {
+        Value::Integer(millis) => Utc
+            .timestamp_millis_opt(*millis)
+            .single()
+            .map(inst_of)
+            .ok_or_else(|| fail(format!("'{}' got out of range millis {}", name, millis))),
+        _ => Err(fail(format!(
+            "Can not apply '{}' to {}",
+            name,
+            value_type_name(value)
+        ))),
+    }
+}
+
+/// `inst`: reads a timestamp string or epoch millis as an `#inst`, and
+/// normalizes an existing `#inst` to UTC.
+crate fn inst(input: Value, _args: Vec<Value>) -> OperationResult {
+    match &input {
+        Value::String(s) => tags::parse_inst(s)
+            .map(|instant| inst_of(instant.with_timezone(&Utc)))
+            .ok_or_else(|| fail(format!("Invalid timestamp '{}'", s))),
+        Value::Integer(_) => from_millis_value("inst", &input),
+        _ => instant("inst", &input).map(inst_of),
+    }
+}
+
+/// `now`: the current time.
+crate fn now(_input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(inst_of(Utc::now()))
+}
+
+/// `to-millis`: milliseconds since the Unix epoch.
+crate fn to_millis(input: Value, _args: Vec<Value>) -> OperationResult {
+    instant("to-millis", &input).map(|i| Value::Integer(i.timestamp_millis()))
+}
+
+/// `from-millis`: the `#inst` for milliseconds since the Unix epoch.
+crate fn from_millis(input: Value, _args: Vec<Value>) -> OperationResult {
+    from_millis_value("from-millis", &input)
+}
+
+/// `duration(other)`: the milliseconds from `other` to the input.
+crate fn duration(input: Value, args: Vec<Value>) -> OperationResult {
+    let end = instant("duration", &input)?;
+    let start = instant("duration", &args[0])?;
+
+    Ok(Value::Integer(end.signed_duration_since(start).num_milliseconds()))
+}
+
+/// `truncate(unit)`: rounds down to the start of the year, month, day, hour,
+/// minute or second, in UTC.
+crate fn truncate(input: Value, args: Vec<Value>) -> OperationResult {
+    let i = instant("truncate", &input)?;
+
+    let unit = match &args[0] {
+        Value::Keyword(unit) | Value::String(unit) => unit.as_str(),
+        other => {
+            return Err(fail(format!(
+                "'truncate' needs a unit such as :day, got {}",
+                value_type_name(other)
+            )))
+        }
+    };
+
+    let truncated = match unit {
+        "year" => Utc.ymd(i.year(), 1, 1).and_hms(0, 0, 0),
+        "month" => Utc.ymd(i.year(), i.month(), 1).and_hms(0, 0, 0),
+        "day" => i.date().and_hms(0, 0, 0),
+        "hour" => i.date().and_hms(i.hour(), 0, 0),
+        "minute" => i.date().and_hms(i.hour(), i.minute(), 0),
+        "second" => i.date().and_hms(i.hour(), i.minute(), i.second()),
+        _ => return Err(fail(format!("Unknown unit '{}' for 'truncate'", unit))),
+    };
+
+    Ok(inst_of(truncated))
+}
+
+/// `format-inst(pattern)`: formats with a strftime pattern, in UTC.
+crate fn format_inst(input: Value, args: Vec<Value>) -> OperationResult {
+    let i = instant("format-inst", &input)?;
+
+    let pattern = match &args[0] {
+        Value::String(pattern) => pattern,
+        other => {
+            return Err(fail(format!(
+                "'format-inst' needs a pattern string, got {}",
+                value_type_name(other)
+            )))
+        }
+    };
+
+    let mut formatted = String::new();
+    write!(formatted, "{}", i.format(pattern))
+        .map_err(|_| fail(format!("Invalid format pattern '{}'", pattern)))?;
+
+    Ok(Value::String(formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inst_str(s: &str) -> Value {
+        Value::Tagged("inst".to_owned(), Box::new(Value::from(s)))
+    }
+
+    #[test]
+    fn test_millis() {
+        let epoch = inst_str("1970-01-01T00:00:01.500Z");
+
+        assert_eq!(to_millis(epoch.clone(), vec![]).unwrap(), Value::Integer(1500));
+        assert_eq!(from_millis(Value::Integer(1500), vec![]).unwrap(), epoch);
+        assert_eq!(inst(Value::from("1970-01-01T01:00:01.5+01:00"), vec![]).unwrap(), epoch);
+        assert!(from_millis(Value::Integer(std::i64::MAX), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_truncate_and_format() {
+        let ts = inst_str("2026-03-04T05:06:07.890Z");
+        let day = truncate(ts.clone(), vec![Value::Keyword("day".to_owned())]).unwrap();
+
+        assert_eq!(day, inst_str("2026-03-04T00:00:00.000Z"));
+        assert_eq!(
+            duration(ts.clone(), vec![day]).unwrap(),
+            Value::Integer(((5 * 60 + 6) * 60 + 7) * 1000 + 890)
+        );
+        assert_eq!(
+            format_inst(ts, vec![Value::from("%Y/%m/%d %H")]).unwrap(),
+            Value::from("2026/03/04 05")
+        );
+    }
+}
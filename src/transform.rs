@@ -1,10 +1,38 @@
-use edn::Value;
+use std::cmp::Ordering;
+use std::mem;
+
+use clap::{_clap_count_exprs, arg_enum};
+use colored::*;
+
+use super::map::Map;
+use super::number;
+use super::parse::Variables;
+use super::tags;
+use super::value::Value;
 
 crate struct TransformOptions {
     crate expression: String,
+    /// Variables bound with `--arg` and `--argedn`.
+    crate variables: Variables,
+}
+
+arg_enum! {
+    /// How a deep merge combines two vectors or lists.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum VectorMerge {
+        Replace,
+        Concat,
+    }
+}
+
+/// Merge mode: the inputs are deep merged in order into a single form, which
+/// the expression then runs on.
+crate struct MergeOptions {
+    crate paths: Vec<String>,
+    crate vectors: VectorMerge,
 }
 
-fn value_type_name(value: &Value) -> String {
+crate fn value_type_name(value: &Value) -> String {
     match value {
         Value::Nil => "nil".to_owned(),
         Value::Boolean(_b) => "a boolean".to_owned(),
@@ -13,19 +41,56 @@ fn value_type_name(value: &Value) -> String {
         Value::Symbol(_s) => "a symbol".to_owned(),
         Value::Keyword(_k) => "a keyword".to_owned(),
         Value::Integer(_i) => "an integer".to_owned(),
+        Value::BigInt(_i) => "a big integer".to_owned(),
         Value::Float(_f) => "a float".to_owned(),
+        Value::BigDecimal(_d) => "a big decimal".to_owned(),
+        Value::Ratio(_r) => "a ratio".to_owned(),
         Value::List(_l) => "a list".to_owned(),
         Value::Vector(_v) => "a vector".to_owned(),
         Value::Map(_m) => "a map".to_owned(),
         Value::Set(_s) => "a set".to_owned(),
-        Value::Tagged(_x, _y) => "a tagged".to_owned(),
+        Value::Tagged(tag, _y) if tag == "inst" => "an instant".to_owned(),
+        Value::Tagged(tag, _y) if tag == "uuid" => "a UUID".to_owned(),
+        Value::Tagged(tag, _y) => format!("a #{} tagged value", tag),
+    }
+}
+
+crate fn fail(message: String) -> OperationError {
+    OperationError::Failed(message)
+}
+
+/// The string a string builtin was applied to, or an error naming the
+/// builtin when the input is anything else.
+crate fn text<'v>(function: &str, input: &'v Value) -> Result<&'v str, OperationError> {
+    match input {
+        Value::String(s) => Ok(s),
+        _ => Err(fail(format!(
+            "Can not apply '{}' to {}",
+            function,
+            value_type_name(input)
+        ))),
     }
 }
 
 #[derive(Debug)]
-crate struct OperationError(String);
+crate enum OperationError {
+    Failed(String),
+    /// The operation produced no value, as `select` does for inputs which do
+    /// not match. Collection operations skip such items, and nothing is
+    /// written for an empty top-level result.
+    Empty,
+}
 
-type OperationResult = Result<Value, OperationError>;
+impl std::fmt::Display for OperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperationError::Failed(message) => write!(f, "{} {}", "error:".red().bold(), message),
+            OperationError::Empty => write!(f, "{} no output", "error:".red().bold()),
+        }
+    }
+}
+
+crate type OperationResult = Result<Value, OperationError>;
 
 crate trait Operation {
     fn execute(&self, input: Value) -> OperationResult;
@@ -46,7 +111,7 @@ impl Operation for KeysOperation {
     fn execute(&self, input: Value) -> OperationResult {
         match input {
             Value::Map(m) => Ok(Value::Vector(m.keys().cloned().collect())),
-            _ => Err(OperationError(format!(
+            _ => Err(OperationError::Failed(format!(
                 "Can not apply 'keys' operation to {}",
                 value_type_name(&input)
             ))),
@@ -60,7 +125,7 @@ impl Operation for ValuesOperation {
     fn execute(&self, input: Value) -> OperationResult {
         match input {
             Value::Map(m) => Ok(Value::Vector(m.values().cloned().collect())),
-            _ => Err(OperationError(format!(
+            _ => Err(OperationError::Failed(format!(
                 "Can not apply 'values' operation to {}",
                 value_type_name(&input)
             ))),
@@ -77,7 +142,7 @@ impl Operation for GetOperation {
     fn execute(&self, input: Value) -> OperationResult {
         match input {
             Value::Map(m) => Ok(m.get(&self.key).unwrap_or(&Value::Nil).clone()),
-            _ => Err(OperationError(format!(
+            _ => Err(OperationError::Failed(format!(
                 "Can not apply 'get' operation to {}",
                 value_type_name(&input)
             ))),
@@ -86,20 +151,12 @@ impl Operation for GetOperation {
 }
 
 crate struct MapOperation {
-    op: Box<dyn Operation>,
+    crate op: Box<dyn Operation>,
 }
 
 impl MapOperation {
     fn do_map(&self, input: Vec<Value>) -> OperationResult {
-        input
-            .into_iter()
-            .try_fold(vec![], |mut state, x| {
-                self.op.execute(x).map(|value| {
-                    state.push(value);
-                    state
-                })
-            })
-            .map(|values| Value::Vector(values))
+        collect_results(input.into_iter().map(|x| self.op.execute(x))).map(Value::Vector)
     }
 }
 
@@ -109,48 +166,367 @@ impl Operation for MapOperation {
             Value::List(l) => self.do_map(l),
             Value::Vector(v) => self.do_map(v),
             Value::Set(s) => self.do_map(s.into_iter().collect()),
-            _ => Err(OperationError(format!(
-                "Can not apply 'get' operation to {}",
+            _ => Err(OperationError::Failed(format!(
+                "Can not apply 'map' operation to {}",
                 value_type_name(&input)
             ))),
         }
     }
 }
 
-fn parse_transform(_transform: &'a str) -> Option<Vec<Box<dyn Operation>>> {
-    /*let x = super::parse::identity(_transform.as_bytes());
+/// Gathers the values of a sequence of results, skipping empty ones and
+/// stopping at the first failure.
+crate fn collect_results(
+    results: impl Iterator<Item = OperationResult>,
+) -> Result<Vec<Value>, OperationError> {
+    let mut values = vec![];
+
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(OperationError::Empty) => {}
+            Err(e) => return Err(e),
+        }
+    }
 
-    match x {
-        Ok(y) => println!("{:?}", y),
-        Err(e) => println!("error: {:?}", e)
-    }*/
+    Ok(values)
+}
+
+/// Ignores its input and produces a fixed value, such as a literal or a
+/// variable bound on the command line.
+crate struct ConstantOperation {
+    crate value: Value,
+}
+
+impl Operation for ConstantOperation {
+    fn execute(&self, _input: Value) -> OperationResult {
+        Ok(self.value.clone())
+    }
+}
+
+/// Builds a vector from the results of running each item on the input.
+crate struct ConstructVectorOperation {
+    crate items: Vec<Box<dyn Operation>>,
+}
 
-    Some(vec![Box::new(IdentityOperation {})])
+impl Operation for ConstructVectorOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        collect_results(self.items.iter().map(|item| item.execute(input.clone())))
+            .map(Value::Vector)
+    }
+}
+
+/// Builds a map with fixed keys from the results of running each value on
+/// the input.
+crate struct ConstructMapOperation {
+    crate entries: Vec<(Value, Box<dyn Operation>)>,
+}
+
+impl Operation for ConstructMapOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        let mut map = Map::new();
+
+        for (key, op) in &self.entries {
+            map.insert(key.clone(), op.execute(input.clone())?);
+        }
+
+        Ok(Value::Map(map))
+    }
+}
+
+/// Applies an arithmetic operator to the results of two sub-operations, both
+/// of which receive the same input.
+crate struct ArithmeticOperation {
+    crate operator: number::Operator,
+    crate lhs: Box<dyn Operation>,
+    crate rhs: Box<dyn Operation>,
+}
+
+impl Operation for ArithmeticOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        let lhs = self.lhs.execute(input.clone())?;
+        let rhs = self.rhs.execute(input)?;
+
+        number::apply(self.operator, &lhs, &rhs).map_err(|e| {
+            OperationError::Failed(format!(
+                "{} (applying '{}' to {} and {})",
+                e,
+                self.operator.name(),
+                value_type_name(&lhs),
+                value_type_name(&rhs)
+            ))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    crate fn name(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// Orders two values by meaning: numbers by value across representations,
+/// instants chronologically, and other values of the same kind naturally.
+/// `None` for values of different kinds, which can not be ordered.
+crate fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    number::compare(a, b)
+        .or_else(|| tags::compare_tagged(a, b))
+        .or_else(|| {
+            if mem::discriminant(a) == mem::discriminant(b) {
+                Some(a.cmp(b))
+            } else {
+                None
+            }
+        })
+}
 
-    /*Some(vec![Box::new(MapOperation {
-        op: Box::new(GetOperation {
-            key: Value::Keyword("abc".to_owned()),
-        }), //Box::new(GetOperation { key: Value::Keyword("abc".to_owned())
-    })])*/
+/// Compares the results of two sub-operations, both of which receive the
+/// same input. Values which can not be ordered are never equal.
+crate struct CompareOperation {
+    crate comparison: Comparison,
+    crate lhs: Box<dyn Operation>,
+    crate rhs: Box<dyn Operation>,
 }
 
-fn transform_form(form: Value, operations: &Vec<Box<dyn Operation>>) -> OperationResult {
+impl Operation for CompareOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        let lhs = self.lhs.execute(input.clone())?;
+        let rhs = self.rhs.execute(input)?;
+
+        match (compare_values(&lhs, &rhs), self.comparison) {
+            (Some(ordering), comparison) => Ok(Value::Boolean(comparison.holds(ordering))),
+            (None, Comparison::Equal) => Ok(Value::Boolean(false)),
+            (None, Comparison::NotEqual) => Ok(Value::Boolean(true)),
+            (None, comparison) => Err(OperationError::Failed(format!(
+                "Can not apply '{}' to {} and {}",
+                comparison.name(),
+                value_type_name(&lhs),
+                value_type_name(&rhs)
+            ))),
+        }
+    }
+}
+
+/// Passes its input through when the predicate holds for it, and otherwise
+/// produces nothing.
+crate struct SelectOperation {
+    crate predicate: Box<dyn Operation>,
+}
+
+impl Operation for SelectOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        if is_truthy(&self.predicate.execute(input.clone())?) {
+            Ok(input)
+        } else {
+            Err(OperationError::Empty)
+        }
+    }
+}
+
+/// Runs a sequence of operations, feeding each one's output to the next.
+crate struct PipelineOperation {
+    crate operations: Vec<Box<dyn Operation>>,
+}
+
+impl Operation for PipelineOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        transform_form(input, &self.operations)
+    }
+}
+
+/// Whether a value counts as true: everything except `nil` and `false`.
+crate fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Nil | Value::Boolean(false) => false,
+        _ => true,
+    }
+}
+
+fn transform_form(form: Value, operations: &[Box<dyn Operation>]) -> OperationResult {
     operations.iter().try_fold(form, |acc, op| op.execute(acc))
 }
 
 crate fn transform_edn(
     forms: Vec<Value>,
-    transform: &TransformOptions,
+    operations: &[Box<dyn Operation>],
 ) -> Result<Vec<Value>, OperationError> {
-    let operations = parse_transform(&transform.expression);
-
-    match operations {
-        None => Ok(forms),
-        Some(ops) => forms.into_iter().try_fold(vec![], |mut acc, form| {
-            transform_form(form, &ops).map(|x| {
-                acc.push(x);
-                acc
-            })
-        }),
+    collect_results(
+        forms
+            .into_iter()
+            .map(|form| transform_form(form, operations)),
+    )
+}
+
+fn merge_items(mut base: Vec<Value>, overlay: Vec<Value>, vectors: VectorMerge) -> Vec<Value> {
+    match vectors {
+        VectorMerge::Replace => overlay,
+        VectorMerge::Concat => {
+            base.extend(overlay);
+            base
+        }
+    }
+}
+
+/// Merges `overlay` into `base`. Maps merge key by key, recursively, sets
+/// take the union of their members and vectors and lists follow `vectors`.
+/// Anything else, including a `nil` overlay, replaces what was there.
+crate fn deep_merge(base: Value, overlay: Value, vectors: VectorMerge) -> Value {
+    match (base, overlay) {
+        (Value::Map(mut base), Value::Map(overlay)) => {
+            for (key, value) in overlay {
+                let merged = match base.get(&key).cloned() {
+                    Some(existing) => deep_merge(existing, value, vectors),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Map(base)
+        }
+        (Value::Set(mut base), Value::Set(overlay)) => {
+            base.extend(overlay);
+            Value::Set(base)
+        }
+        (Value::Vector(base), Value::Vector(overlay)) => {
+            Value::Vector(merge_items(base, overlay, vectors))
+        }
+        (Value::List(base), Value::List(overlay)) => {
+            Value::List(merge_items(base, overlay, vectors))
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+fn merge_policy(value: &Value) -> Result<VectorMerge, OperationError> {
+    match value {
+        Value::Keyword(name) | Value::String(name) => name.parse().map_err(OperationError::Failed),
+        _ => Err(OperationError::Failed(format!(
+            "'deep-merge' needs :replace or :concat, got {}",
+            value_type_name(value)
+        ))),
+    }
+}
+
+/// `merge(other)`: the input map with the entries of another map added,
+/// replacing those with the same keys.
+crate fn merge(input: Value, args: Vec<Value>) -> OperationResult {
+    match (input, &args[0]) {
+        (Value::Map(mut base), Value::Map(overlay)) => {
+            for (k, v) in overlay.iter() {
+                base.insert(k.clone(), v.clone());
+            }
+            Ok(Value::Map(base))
+        }
+        (Value::Nil, Value::Map(_)) => Ok(args[0].clone()),
+        (base, overlay) => Err(OperationError::Failed(format!(
+            "Can not merge {} into {}",
+            value_type_name(overlay),
+            value_type_name(&base)
+        ))),
+    }
+}
+
+/// `deep-merge`: deep merges the items of a vector, such as the forms read
+/// with `--slurp`, in order. With arguments, `deep-merge(other)` or
+/// `deep-merge(other; :concat)`, merges another value into the input.
+crate fn deep_merge_all(input: Value, args: Vec<Value>) -> OperationResult {
+    let mut args = args.into_iter();
+    let overlay = args.next();
+    let vectors = match args.next() {
+        Some(policy) => merge_policy(&policy)?,
+        None => VectorMerge::Replace,
+    };
+
+    match (input, overlay) {
+        (base, Some(overlay)) => Ok(deep_merge(base, overlay, vectors)),
+        (Value::List(items), None) | (Value::Vector(items), None) => Ok(items
+            .into_iter()
+            .fold(Value::Nil, |base, item| deep_merge(base, item, vectors))),
+        (input, None) => Err(OperationError::Failed(format!(
+            "Can not apply 'deep-merge' to {}",
+            value_type_name(&input)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_of(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Value::Keyword(k.to_owned()), v))
+                .collect(),
+        )
+    }
+
+    fn ints(items: Vec<i64>) -> Value {
+        Value::Vector(items.into_iter().map(Value::Integer).collect())
+    }
+
+    #[test]
+    fn test_deep_merge() {
+        let base = map_of(vec![
+            ("db", map_of(vec![("host", Value::from("db")), ("port", Value::Integer(1))])),
+            ("ports", ints(vec![80])),
+        ]);
+        let env = map_of(vec![
+            ("db", map_of(vec![("port", Value::Integer(2))])),
+            ("ports", ints(vec![443])),
+        ]);
+        let merged_db = map_of(vec![("host", Value::from("db")), ("port", Value::Integer(2))]);
+
+        assert_eq!(
+            deep_merge(base.clone(), env.clone(), VectorMerge::Replace),
+            map_of(vec![("db", merged_db.clone()), ("ports", ints(vec![443]))])
+        );
+        assert_eq!(
+            deep_merge(base, env, VectorMerge::Concat),
+            map_of(vec![("db", merged_db), ("ports", ints(vec![80, 443]))])
+        );
+    }
+
+    #[test]
+    fn test_deep_merge_all() {
+        let layers = Value::Vector(vec![
+            map_of(vec![("a", Value::Integer(1))]),
+            map_of(vec![("b", Value::Integer(2))]),
+            map_of(vec![("a", Value::Integer(3))]),
+        ]);
+
+        assert_eq!(
+            deep_merge_all(layers, vec![]).unwrap(),
+            map_of(vec![("a", Value::Integer(3)), ("b", Value::Integer(2))])
+        );
+        assert!(deep_merge_all(Value::Nil, vec![Value::Nil, Value::from("append")]).is_err());
     }
 }
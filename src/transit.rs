@@ -0,0 +1,557 @@
+use std::collections::{BTreeSet, HashMap};
+
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
+use serde_json::Number as JsonNumber;
+use serde_json::Value as JsonValue;
+
+use super::map::Map;
+use super::number;
+use super::value::Value as EdnValue;
+
+/// Number of distinct cache codes available before the cache is reset.
+const CACHE_SIZE: usize = 44 * 44;
+const CACHE_CODE_DIGITS: usize = 44;
+const CACHE_CODE_BASE: u8 = 48;
+
+/// Largest integer which can be written as a bare JSON number without losing
+/// precision in JavaScript readers.
+const MAX_JSON_INT: i64 = (1 << 53) - 1;
+
+const MAP_AS_ARRAY: &str = "^ ";
+
+#[derive(Debug)]
+crate struct TransitError(String);
+
+impl std::fmt::Display for TransitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+type TransitResult<T> = Result<T, TransitError>;
+
+fn is_cacheable(s: &str, as_map_key: bool) -> bool {
+    s.len() > 3 && (as_map_key || s.starts_with("~:") || s.starts_with("~$") || s.starts_with("~#"))
+}
+
+fn index_to_code(index: usize) -> String {
+    let hi = index / CACHE_CODE_DIGITS;
+    let lo = index % CACHE_CODE_DIGITS;
+
+    if hi == 0 {
+        format!("^{}", (lo as u8 + CACHE_CODE_BASE) as char)
+    } else {
+        format!(
+            "^{}{}",
+            (hi as u8 + CACHE_CODE_BASE) as char,
+            (lo as u8 + CACHE_CODE_BASE) as char
+        )
+    }
+}
+
+fn code_to_index(code: &str) -> Option<usize> {
+    let digits: Vec<usize> = code
+        .bytes()
+        .skip(1)
+        .map(|b| b.wrapping_sub(CACHE_CODE_BASE) as usize)
+        .collect();
+
+    match digits.as_slice() {
+        [lo] if *lo < CACHE_CODE_DIGITS => Some(*lo),
+        [hi, lo] if *hi < CACHE_CODE_DIGITS && *lo < CACHE_CODE_DIGITS => {
+            Some(hi * CACHE_CODE_DIGITS + lo)
+        }
+        _ => None,
+    }
+}
+
+fn millis_to_inst(millis: i64) -> Option<EdnValue> {
+    let instant = Utc.timestamp_millis_opt(millis).single()?;
+
+    Some(EdnValue::Tagged(
+        "inst".to_owned(),
+        Box::new(EdnValue::String(
+            instant.to_rfc3339_opts(SecondsFormat::Millis, true),
+        )),
+    ))
+}
+
+/// A decoded Transit string: either a plain value, or a `~#tag` marker which
+/// introduces a tagged composite.
+#[derive(Clone)]
+enum Decoded {
+    Value(EdnValue),
+    Tag(String),
+}
+
+struct Decoder {
+    cache: Vec<Decoded>,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Decoder { cache: vec![] }
+    }
+
+    fn remember(&mut self, decoded: &Decoded) {
+        if self.cache.len() == CACHE_SIZE {
+            self.cache.clear();
+        }
+        self.cache.push(decoded.clone());
+    }
+
+    fn decode_string(&mut self, s: String, as_map_key: bool) -> TransitResult<Decoded> {
+        if s.starts_with('^') && s != MAP_AS_ARRAY {
+            return code_to_index(&s)
+                .and_then(|idx| self.cache.get(idx).cloned())
+                .ok_or_else(|| TransitError(format!("Unknown cache reference '{}'", s)));
+        }
+
+        let decoded = parse_string(&s)?;
+        if is_cacheable(&s, as_map_key) {
+            self.remember(&decoded);
+        }
+
+        Ok(decoded)
+    }
+
+    fn decode_scalar(&mut self, s: String, as_map_key: bool) -> TransitResult<EdnValue> {
+        match self.decode_string(s, as_map_key)? {
+            Decoded::Value(v) => Ok(v),
+            Decoded::Tag(t) => Err(TransitError(format!("Unexpected tag '{}'", t))),
+        }
+    }
+
+    fn decode_value(&mut self, json: JsonValue, as_map_key: bool) -> TransitResult<EdnValue> {
+        match json {
+            JsonValue::String(s) => self.decode_scalar(s, as_map_key),
+            other => self.decode(other),
+        }
+    }
+
+    fn decode(&mut self, json: JsonValue) -> TransitResult<EdnValue> {
+        match json {
+            JsonValue::Null => Ok(EdnValue::Nil),
+            JsonValue::Bool(b) => Ok(EdnValue::Boolean(b)),
+            JsonValue::Number(n) => decode_number(&n),
+            JsonValue::String(s) => self.decode_scalar(s, false),
+            JsonValue::Array(items) => self.decode_array(items),
+            JsonValue::Object(entries) => self.decode_object(entries.into_iter().collect()),
+        }
+    }
+
+    fn decode_array(&mut self, items: Vec<JsonValue>) -> TransitResult<EdnValue> {
+        let mut items = items.into_iter();
+
+        match items.next() {
+            None => Ok(EdnValue::Vector(vec![])),
+            Some(JsonValue::String(ref s)) if s == MAP_AS_ARRAY => {
+                self.decode_map_entries(items.collect())
+            }
+            Some(JsonValue::String(s)) => match self.decode_string(s, false)? {
+                Decoded::Tag(tag) => {
+                    if items.len() != 1 {
+                        return Err(TransitError(format!(
+                            "Tag '{}' must be followed by exactly one value",
+                            tag
+                        )));
+                    }
+                    self.decode_tagged(tag, items.next().unwrap())
+                }
+                Decoded::Value(first) => {
+                    let mut acc = vec![first];
+                    for item in items {
+                        acc.push(self.decode(item)?);
+                    }
+                    Ok(EdnValue::Vector(acc))
+                }
+            },
+            Some(first) => {
+                let mut acc = vec![self.decode(first)?];
+                for item in items {
+                    acc.push(self.decode(item)?);
+                }
+                Ok(EdnValue::Vector(acc))
+            }
+        }
+    }
+
+    /// Decodes the verbose (JSON-Verbose) encoding, where maps are JSON objects
+    /// and tagged values are single-entry objects.
+    fn decode_object(&mut self, entries: Vec<(String, JsonValue)>) -> TransitResult<EdnValue> {
+        if entries.len() == 1 && entries[0].0.starts_with("~#") {
+            let (tag, value) = entries.into_iter().next().unwrap();
+            return self.decode_tagged(tag[2..].to_owned(), value);
+        }
+
+        let mut acc = Map::new();
+        for (k, v) in entries {
+            let key = self.decode_scalar(k, true)?;
+            acc.insert(key, self.decode(v)?);
+        }
+
+        Ok(EdnValue::Map(acc))
+    }
+
+    fn decode_map_entries(&mut self, items: Vec<JsonValue>) -> TransitResult<EdnValue> {
+        if items.len() % 2 != 0 {
+            return Err(TransitError("Map has an odd number of elements".into()));
+        }
+
+        let mut acc = Map::new();
+        let mut items = items.into_iter();
+        while let (Some(k), Some(v)) = (items.next(), items.next()) {
+            let key = self.decode_value(k, true)?;
+            acc.insert(key, self.decode(v)?);
+        }
+
+        Ok(EdnValue::Map(acc))
+    }
+
+    fn decode_elements(&mut self, value: JsonValue, tag: &str) -> TransitResult<Vec<EdnValue>> {
+        match value {
+            JsonValue::Array(items) => items.into_iter().map(|i| self.decode(i)).collect(),
+            _ => Err(TransitError(format!("Tag '{}' requires an array", tag))),
+        }
+    }
+
+    fn decode_tagged(&mut self, tag: String, value: JsonValue) -> TransitResult<EdnValue> {
+        match tag.as_str() {
+            "'" => self.decode(value),
+            "set" => Ok(EdnValue::Set(
+                self.decode_elements(value, &tag)?
+                    .into_iter()
+                    .collect::<BTreeSet<_>>(),
+            )),
+            "list" => Ok(EdnValue::List(self.decode_elements(value, &tag)?)),
+            "ratio" => decode_ratio(self.decode_elements(value, &tag)?),
+            "cmap" => {
+                let items = self.decode_elements(value, &tag)?;
+                if items.len() % 2 != 0 {
+                    return Err(TransitError("Map has an odd number of elements".into()));
+                }
+
+                let mut acc = Map::new();
+                let mut items = items.into_iter();
+                while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                    acc.insert(k, v);
+                }
+                Ok(EdnValue::Map(acc))
+            }
+            _ => Ok(EdnValue::Tagged(tag, Box::new(self.decode(value)?))),
+        }
+    }
+}
+
+fn decode_number(n: &JsonNumber) -> TransitResult<EdnValue> {
+    Ok(number::from_json(n))
+}
+
+fn decode_bigint(s: &str) -> Option<BigInt> {
+    BigInt::from_str(s).ok()
+}
+
+fn decode_ratio(parts: Vec<EdnValue>) -> TransitResult<EdnValue> {
+    let as_bigint = |v: &EdnValue| match v {
+        EdnValue::Integer(i) => Some(BigInt::from(*i)),
+        EdnValue::BigInt(i) => Some(i.clone()),
+        _ => None,
+    };
+
+    match parts.as_slice() {
+        [numer, denom] => match (as_bigint(numer), as_bigint(denom)) {
+            (Some(ref n), Some(ref d)) if !d.is_zero() => {
+                Ok(EdnValue::Ratio(BigRational::new(n.clone(), d.clone())))
+            }
+            _ => Err(TransitError("Invalid ratio".into())),
+        },
+        _ => Err(TransitError("Ratio requires a numerator and a denominator".into())),
+    }
+}
+
+fn parse_string(s: &str) -> TransitResult<Decoded> {
+    let mut chars = s.chars();
+    let marker = match (chars.next(), chars.next()) {
+        (Some('~'), Some(marker)) => marker,
+        _ => return Ok(Decoded::Value(EdnValue::String(s.to_owned()))),
+    };
+
+    let rest = chars.as_str();
+    let invalid = || TransitError(format!("Invalid Transit value '{}'", s));
+
+    let value = match marker {
+        '~' | '^' | '`' => EdnValue::String(s[1..].to_owned()),
+        '#' => return Ok(Decoded::Tag(rest.to_owned())),
+        ':' => EdnValue::Keyword(rest.to_owned()),
+        '$' => EdnValue::Symbol(rest.to_owned()),
+        '_' => EdnValue::Nil,
+        '?' => EdnValue::Boolean(rest == "t"),
+        'i' => match rest.parse::<i64>() {
+            Ok(i) => EdnValue::Integer(i),
+            Err(_) => EdnValue::BigInt(decode_bigint(rest).ok_or_else(invalid)?),
+        },
+        'n' => EdnValue::BigInt(decode_bigint(rest).ok_or_else(invalid)?),
+        'd' => EdnValue::from(rest.parse::<f64>().map_err(|_| invalid())?),
+        'f' => EdnValue::BigDecimal(BigDecimal::from_str(rest).map_err(|_| invalid())?),
+        'c' => EdnValue::Char(rest.chars().next().ok_or_else(invalid)?),
+        'z' => EdnValue::from(match rest {
+            "NaN" => std::f64::NAN,
+            "INF" => std::f64::INFINITY,
+            "-INF" => std::f64::NEG_INFINITY,
+            _ => return Err(invalid()),
+        }),
+        'u' => EdnValue::Tagged("uuid".to_owned(), Box::new(EdnValue::String(rest.to_owned()))),
+        't' => EdnValue::Tagged("inst".to_owned(), Box::new(EdnValue::String(rest.to_owned()))),
+        'm' => {
+            let millis = rest.parse::<i64>().map_err(|_| invalid())?;
+            millis_to_inst(millis).ok_or_else(invalid)?
+        }
+        'r' | 'b' => EdnValue::String(rest.to_owned()),
+        tag => EdnValue::Tagged(tag.to_string(), Box::new(EdnValue::String(rest.to_owned()))),
+    };
+
+    Ok(Decoded::Value(value))
+}
+
+/// Decodes a Transit+JSON document which has already been read by serde_json.
+crate fn decode(json: JsonValue) -> TransitResult<EdnValue> {
+    Decoder::new().decode(json)
+}
+
+struct Encoder {
+    cache: HashMap<String, String>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder {
+            cache: HashMap::new(),
+        }
+    }
+
+    fn cached(&mut self, s: String, as_map_key: bool) -> JsonValue {
+        if !is_cacheable(&s, as_map_key) {
+            return JsonValue::String(s);
+        }
+
+        if let Some(code) = self.cache.get(&s) {
+            return JsonValue::String(code.clone());
+        }
+
+        if self.cache.len() == CACHE_SIZE {
+            self.cache.clear();
+        }
+        let code = index_to_code(self.cache.len());
+        self.cache.insert(s.clone(), code);
+
+        JsonValue::String(s)
+    }
+
+    fn tagged(&mut self, tag: &str, value: JsonValue) -> JsonValue {
+        JsonValue::Array(vec![self.cached(format!("~#{}", tag), false), value])
+    }
+
+    fn encode_top_level(&mut self, value: EdnValue) -> JsonValue {
+        match value {
+            EdnValue::List(_)
+            | EdnValue::Vector(_)
+            | EdnValue::Map(_)
+            | EdnValue::Set(_) => self.encode(value, false),
+            EdnValue::Tagged(ref tag, _) if tag != "inst" && tag != "uuid" => {
+                self.encode(value, false)
+            }
+            scalar => {
+                let encoded = self.encode(scalar, false);
+                self.tagged("'", encoded)
+            }
+        }
+    }
+
+    fn encode(&mut self, value: EdnValue, as_map_key: bool) -> JsonValue {
+        match value {
+            EdnValue::Nil if as_map_key => JsonValue::String("~_".to_owned()),
+            EdnValue::Nil => JsonValue::Null,
+            EdnValue::Boolean(b) if as_map_key => {
+                JsonValue::String(format!("~?{}", if b { "t" } else { "f" }))
+            }
+            EdnValue::Boolean(b) => JsonValue::Bool(b),
+            EdnValue::String(s) => {
+                let escaped = if s.starts_with('~') || s.starts_with('^') || s.starts_with('`') {
+                    format!("~{}", s)
+                } else {
+                    s
+                };
+                self.cached(escaped, as_map_key)
+            }
+            EdnValue::Char(c) => JsonValue::String(format!("~c{}", c)),
+            EdnValue::Symbol(s) => self.cached(format!("~${}", s), as_map_key),
+            EdnValue::Keyword(k) => self.cached(format!("~:{}", k), as_map_key),
+            EdnValue::Integer(i) => {
+                if as_map_key || i > MAX_JSON_INT || i < -MAX_JSON_INT {
+                    JsonValue::String(format!("~i{}", i))
+                } else {
+                    JsonValue::from(i)
+                }
+            }
+            EdnValue::BigInt(i) => JsonValue::String(format!("~n{}", i)),
+            EdnValue::Float(f) => encode_float(f.into_inner(), as_map_key),
+            EdnValue::BigDecimal(d) => JsonValue::String(format!("~f{}", d)),
+            EdnValue::Ratio(r) => {
+                let parts = vec![
+                    JsonValue::String(format!("~n{}", r.numer())),
+                    JsonValue::String(format!("~n{}", r.denom())),
+                ];
+                self.tagged("ratio", JsonValue::Array(parts))
+            }
+            EdnValue::Vector(v) => {
+                JsonValue::Array(v.into_iter().map(|i| self.encode(i, false)).collect())
+            }
+            EdnValue::List(l) => {
+                let items = l.into_iter().map(|i| self.encode(i, false)).collect();
+                self.tagged("list", JsonValue::Array(items))
+            }
+            EdnValue::Set(s) => {
+                let items = s.into_iter().map(|i| self.encode(i, false)).collect();
+                self.tagged("set", JsonValue::Array(items))
+            }
+            EdnValue::Map(m) => self.encode_map(m),
+            EdnValue::Tagged(tag, inner) => self.encode_tagged(tag, *inner),
+        }
+    }
+
+    fn encode_map(&mut self, map: Map) -> JsonValue {
+        if map.keys().all(is_stringable) {
+            let mut acc = vec![JsonValue::String(MAP_AS_ARRAY.to_owned())];
+            for (k, v) in map {
+                acc.push(self.encode(k, true));
+                acc.push(self.encode(v, false));
+            }
+            JsonValue::Array(acc)
+        } else {
+            let mut acc = vec![];
+            for (k, v) in map {
+                acc.push(self.encode(k, false));
+                acc.push(self.encode(v, false));
+            }
+            self.tagged("cmap", JsonValue::Array(acc))
+        }
+    }
+
+    fn encode_tagged(&mut self, tag: String, inner: EdnValue) -> JsonValue {
+        match (tag.as_str(), inner) {
+            ("inst", EdnValue::String(s)) => match DateTime::parse_from_rfc3339(&s) {
+                Ok(dt) => JsonValue::String(format!("~m{}", dt.timestamp_millis())),
+                Err(_) => JsonValue::String(format!("~t{}", s)),
+            },
+            ("uuid", EdnValue::String(s)) => JsonValue::String(format!("~u{}", s)),
+            (_, inner) => {
+                let encoded = self.encode(inner, false);
+                self.tagged(&tag, encoded)
+            }
+        }
+    }
+}
+
+fn encode_float(f: f64, as_map_key: bool) -> JsonValue {
+    if f.is_nan() {
+        JsonValue::String("~zNaN".to_owned())
+    } else if f.is_infinite() {
+        JsonValue::String(if f > 0.0 { "~zINF" } else { "~z-INF" }.to_owned())
+    } else if as_map_key {
+        JsonValue::String(format!("~d{}", f))
+    } else {
+        JsonNumber::from_f64(f).map_or(JsonValue::Null, JsonValue::Number)
+    }
+}
+
+fn is_stringable(key: &EdnValue) -> bool {
+    match key {
+        EdnValue::List(_)
+        | EdnValue::Vector(_)
+        | EdnValue::Map(_)
+        | EdnValue::Set(_)
+        | EdnValue::Ratio(_) => false,
+        EdnValue::Tagged(tag, _) => tag == "inst" || tag == "uuid",
+        _ => true,
+    }
+}
+
+/// Encodes a single top-level form as Transit+JSON, using a fresh write cache.
+crate fn encode(value: EdnValue) -> JsonValue {
+    Encoder::new().encode_top_level(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_codes() {
+        for idx in &[0, 1, 43, 44, 100, CACHE_SIZE - 1] {
+            assert_eq!(code_to_index(&index_to_code(*idx)), Some(*idx));
+        }
+    }
+
+    #[test]
+    fn test_decode_map_as_array() {
+        let decoded = decode(json!([
+            ["^ ", "~:name", "eq", "~:tags", ["~#set", ["~$a"]]],
+            ["^ ", "^0", "other", "^1", ["^2", []]]
+        ]))
+        .unwrap();
+
+        let first = EdnValue::Map(
+            vec![
+                (EdnValue::Keyword("name".into()), EdnValue::String("eq".into())),
+                (
+                    EdnValue::Keyword("tags".into()),
+                    EdnValue::Set(vec![EdnValue::Symbol("a".into())].into_iter().collect()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        match decoded {
+            EdnValue::Vector(v) => assert_eq!(v[0], first),
+            _ => panic!("expected a vector"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = EdnValue::List(vec![
+            EdnValue::Keyword("a/b".into()),
+            EdnValue::Keyword("a/b".into()),
+            EdnValue::String("~tilde".into()),
+            EdnValue::Tagged(
+                "uuid".into(),
+                Box::new(EdnValue::String(
+                    "5d1e0c6a-8d6e-4d16-9d2c-3e1f2b8f6c1a".into(),
+                )),
+            ),
+            EdnValue::Integer(1 << 60),
+        ]);
+
+        assert_eq!(decode(encode(value.clone())).unwrap(), value);
+    }
+
+    #[test]
+    fn test_decode_invalid_strings() {
+        assert_eq!(
+            decode(json!(["~é", "~"])).unwrap(),
+            EdnValue::Vector(vec![
+                EdnValue::Tagged("é".into(), Box::new(EdnValue::String("".into()))),
+                EdnValue::String("~".into()),
+            ])
+        );
+        assert!(decode(json!("~m9223372036854775807")).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use ordered_float::OrderedFloat;
+
+use super::map::{self, Map};
+use super::number;
+
+/// An EDN value as seen by eq.
+///
+/// This mirrors `edn::Value`, but adds the arbitrary-precision numeric types
+/// (`123N`, `1.50M` and `1/3`) which the `edn` crate has no representation
+/// for.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+crate enum Value {
+    Nil,
+    Boolean(bool),
+    String(String),
+    Char(char),
+    Symbol(String),
+    Keyword(String),
+    Integer(i64),
+    BigInt(BigInt),
+    Float(OrderedFloat<f64>),
+    BigDecimal(BigDecimal),
+    Ratio(BigRational),
+    List(Vec<Value>),
+    Vector(Vec<Value>),
+    Map(Map),
+    Set(BTreeSet<Value>),
+    Tagged(String, Box<Value>),
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(OrderedFloat(f))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<edn::Value> for Value {
+    fn from(value: edn::Value) -> Self {
+        match value {
+            edn::Value::Nil => Value::Nil,
+            edn::Value::Boolean(b) => Value::Boolean(b),
+            edn::Value::String(s) => Value::String(s),
+            edn::Value::Char(c) => Value::Char(c),
+            edn::Value::Symbol(s) => Value::Symbol(s),
+            edn::Value::Keyword(k) => Value::Keyword(k),
+            edn::Value::Integer(i) => Value::Integer(i),
+            edn::Value::Float(f) => {
+                let f: f64 = f.into();
+                Value::from(f)
+            }
+            edn::Value::List(l) => Value::List(l.into_iter().map(Value::from).collect()),
+            edn::Value::Vector(v) => Value::Vector(v.into_iter().map(Value::from).collect()),
+            edn::Value::Map(m) => Value::Map(
+                m.into_iter()
+                    .map(|(k, v)| (Value::from(k), Value::from(v)))
+                    .collect(),
+            ),
+            edn::Value::Set(s) => Value::Set(s.into_iter().map(Value::from).collect()),
+            edn::Value::Tagged(tag, inner) => match (tag.as_str(), *inner) {
+                (number::LITERAL_TAG, edn::Value::String(literal)) => {
+                    number::parse_literal(&literal).unwrap_or(Value::String(literal))
+                }
+                (map::EDN_MAP_TAG, edn::Value::Vector(items)) => {
+                    // The first item is the offset of the map in the input.
+                    let mut items = items.into_iter().skip(1).map(Value::from);
+                    let mut entries = Map::new();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.insert(key, value);
+                    }
+                    Value::Map(entries)
+                }
+                (_, inner) => Value::Tagged(tag, Box::new(Value::from(inner))),
+            },
+        }
+    }
+}
+
+/// Position of each kind of value in the canonical order.
+fn canonical_rank(value: &Value) -> u8 {
+    match value {
+        Value::Nil => 0,
+        Value::Boolean(_) => 1,
+        Value::Integer(_)
+        | Value::BigInt(_)
+        | Value::Float(_)
+        | Value::BigDecimal(_)
+        | Value::Ratio(_) => 2,
+        Value::Char(_) => 3,
+        Value::String(_) => 4,
+        Value::Keyword(_) => 5,
+        Value::Symbol(_) => 6,
+        Value::List(_) => 7,
+        Value::Vector(_) => 8,
+        Value::Map(_) => 9,
+        Value::Set(_) => 10,
+        Value::Tagged(_, _) => 11,
+    }
+}
+
+fn is_nan(value: &Value) -> bool {
+    match value {
+        Value::Float(f) => f.into_inner().is_nan(),
+        _ => false,
+    }
+}
+
+fn canonical_cmp_seq(
+    mut a: impl Iterator<Item = &'a Value>,
+    mut b: impl Iterator<Item = &'a Value>,
+) -> Ordering {
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match canonical_cmp(x, y) {
+                Ordering::Equal => continue,
+                unequal => return unequal,
+            },
+        }
+    }
+}
+
+/// A map's entries sorted by key in canonical order.
+crate fn canonical_entries(map: &Map) -> Vec<(&Value, &Value)> {
+    let mut entries: Vec<(&Value, &Value)> = map.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| canonical_cmp(a, b));
+    entries
+}
+
+/// A set's items sorted in canonical order.
+crate fn canonical_items(set: &BTreeSet<Value>) -> Vec<&Value> {
+    let mut items: Vec<&Value> = set.iter().collect();
+    items.sort_by(|a, b| canonical_cmp(a, b));
+    items
+}
+
+/// The documented, stable order used by canonical output.
+///
+/// Values of different kinds sort as nil, booleans, numbers, characters,
+/// strings, keywords, symbols, lists, vectors, maps, sets and finally tagged
+/// values. Numbers compare by value regardless of representation (ties are
+/// broken by representation) and NaN sorts after every other number.
+/// Collections compare element by element in canonical order, maps by their
+/// sorted entries, and tagged values by tag and then by value.
+crate fn canonical_cmp(a: &Value, b: &Value) -> Ordering {
+    canonical_rank(a)
+        .cmp(&canonical_rank(b))
+        .then_with(|| match (a, b) {
+            (Value::List(x), Value::List(y)) | (Value::Vector(x), Value::Vector(y)) => {
+                canonical_cmp_seq(x.iter(), y.iter())
+            }
+            (Value::Map(x), Value::Map(y)) => canonical_cmp_seq(
+                canonical_entries(x).into_iter().flat_map(|(k, v)| vec![k, v]),
+                canonical_entries(y).into_iter().flat_map(|(k, v)| vec![k, v]),
+            ),
+            (Value::Set(x), Value::Set(y)) => canonical_cmp_seq(
+                canonical_items(x).into_iter(),
+                canonical_items(y).into_iter(),
+            ),
+            (Value::Tagged(t1, v1), Value::Tagged(t2, v2)) => {
+                t1.cmp(t2).then_with(|| canonical_cmp(v1, v2))
+            }
+            _ => is_nan(a).cmp(&is_nan(b)).then_with(|| match number::compare(a, b) {
+                Some(Ordering::Equal) | None => a.cmp(b),
+                Some(unequal) => unequal,
+            }),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_cmp() {
+        let mut values = vec![
+            Value::Keyword("a".to_owned()),
+            Value::from("b"),
+            Value::from(2.5),
+            Value::Integer(10),
+            Value::Nil,
+            Value::Integer(2),
+            Value::Boolean(false),
+        ];
+        values.sort_by(canonical_cmp);
+
+        assert_eq!(
+            values,
+            vec![
+                Value::Nil,
+                Value::Boolean(false),
+                Value::Integer(2),
+                Value::from(2.5),
+                Value::Integer(10),
+                Value::from("b"),
+                Value::Keyword("a".to_owned()),
+            ]
+        );
+    }
+}
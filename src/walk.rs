@@ -0,0 +1,169 @@
+use super::map::Map;
+use super::transform::*;
+use super::value::Value;
+
+/// Every value within a form, the form itself first and then each child in
+/// turn, depth first: collection items, map values (and their keys, when
+/// `keys` is set) and the values inside tagged literals.
+crate fn descendants(value: Value, keys: bool) -> Vec<Value> {
+    let mut found = vec![];
+    collect_descendants(value, keys, &mut found);
+    found
+}
+
+fn collect_descendants(value: Value, keys: bool, found: &mut Vec<Value>) {
+    found.push(value.clone());
+
+    match value {
+        Value::List(items) | Value::Vector(items) => {
+            for item in items {
+                collect_descendants(item, keys, found);
+            }
+        }
+        Value::Set(items) => {
+            for item in items {
+                collect_descendants(item, keys, found);
+            }
+        }
+        Value::Map(m) => {
+            for (k, v) in m {
+                if keys {
+                    collect_descendants(k, keys, found);
+                }
+                collect_descendants(v, keys, found);
+            }
+        }
+        Value::Tagged(_, inner) => collect_descendants(*inner, keys, found),
+        _ => {}
+    }
+}
+
+/// `recurse`, also written `..`: a vector of every value within the input.
+crate fn recurse(input: Value, _args: Vec<Value>) -> OperationResult {
+    Ok(Value::Vector(descendants(input, false)))
+}
+
+/// `recurse(keys)`: as `recurse`, also including map keys when `keys` is
+/// truthy.
+crate fn recurse_keys(input: Value, args: Vec<Value>) -> OperationResult {
+    Ok(Value::Vector(descendants(input, is_truthy(&args[0]))))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+crate enum WalkOrder {
+    /// Apply the operation to a node, then walk the children of its result.
+    Pre,
+    /// Walk the children of a node, then apply the operation to the result.
+    Post,
+}
+
+/// Rebuilds a form, running an operation on every node, as `clojure.walk`
+/// does. Map entries are visited as `[key value]` vectors and must still be
+/// such vectors afterwards. A node for which the operation produces nothing
+/// is left out of its parent.
+crate struct WalkOperation {
+    crate order: WalkOrder,
+    crate op: Box<dyn Operation>,
+}
+
+impl WalkOperation {
+    fn walk(&self, value: Value) -> OperationResult {
+        match self.order {
+            WalkOrder::Pre => self.walk_children(self.op.execute(value)?),
+            WalkOrder::Post => self.op.execute(self.walk_children(value)?),
+        }
+    }
+
+    fn walk_all(&self, items: Vec<Value>) -> Result<Vec<Value>, OperationError> {
+        collect_results(items.into_iter().map(|item| self.walk(item)))
+    }
+
+    fn walk_children(&self, value: Value) -> OperationResult {
+        match value {
+            Value::List(items) => self.walk_all(items).map(Value::List),
+            Value::Vector(items) => self.walk_all(items).map(Value::Vector),
+            Value::Set(items) => self
+                .walk_all(items.into_iter().collect())
+                .map(|items| Value::Set(items.into_iter().collect())),
+            Value::Map(m) => {
+                let entries = m.into_iter().map(|(k, v)| Value::Vector(vec![k, v]));
+
+                self.walk_all(entries.collect())?
+                    .into_iter()
+                    .map(map_entry)
+                    .collect::<Result<Map, _>>()
+                    .map(Value::Map)
+            }
+            Value::Tagged(tag, inner) => Ok(Value::Tagged(tag, Box::new(self.walk(*inner)?))),
+            other => Ok(other),
+        }
+    }
+}
+
+fn map_entry(entry: Value) -> Result<(Value, Value), OperationError> {
+    match entry {
+        Value::Vector(ref pair) if pair.len() == 2 => Ok((pair[0].clone(), pair[1].clone())),
+        _ => Err(OperationError::Failed(format!(
+            "A walk must leave map entries as [key value] vectors, got {}",
+            value_type_name(&entry)
+        ))),
+    }
+}
+
+impl Operation for WalkOperation {
+    fn execute(&self, input: Value) -> OperationResult {
+        self.walk(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kw(name: &str) -> Value {
+        Value::Keyword(name.to_owned())
+    }
+
+    /// Increments integers and leaves everything else alone.
+    struct Increment;
+
+    impl Operation for Increment {
+        fn execute(&self, input: Value) -> OperationResult {
+            match input {
+                Value::Integer(i) => Ok(Value::Integer(i + 1)),
+                other => Ok(other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_descendants() {
+        let items = Value::Vector(vec![Value::Integer(1)]);
+        let form = Value::Map(vec![(kw("a"), items.clone())].into_iter().collect());
+
+        assert_eq!(
+            descendants(form.clone(), false),
+            vec![form.clone(), items, Value::Integer(1)]
+        );
+        assert_eq!(descendants(form, true)[1], kw("a"));
+    }
+
+    #[test]
+    fn test_walk() {
+        let form = Value::Map(
+            vec![(Value::Integer(1), Value::Tagged("t".to_owned(), Box::new(Value::Integer(2))))]
+                .into_iter()
+                .collect(),
+        );
+        let walked = Value::Map(
+            vec![(Value::Integer(2), Value::Tagged("t".to_owned(), Box::new(Value::Integer(3))))]
+                .into_iter()
+                .collect(),
+        );
+
+        for order in vec![WalkOrder::Pre, WalkOrder::Post] {
+            let op = WalkOperation { order, op: Box::new(Increment) };
+            assert_eq!(op.execute(form.clone()).unwrap(), walked);
+        }
+    }
+}